@@ -13,7 +13,11 @@ fn main() {
     let app = Application::new("rust-test", &license).unwrap();
     for _ in 0..120 {
         let txn = app.start_transaction("test");
-        sleep(Duration::from_millis(500));
+        sleep(Duration::from_millis(200));
+        {
+            let _segment = txn.start_segment("sleep");
+            sleep(Duration::from_millis(300));
+        }
         drop(txn);
         sleep(Duration::from_millis(500));
     }
@@ -0,0 +1,126 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use parking_lot::Mutex;
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::priority::Priority;
+
+/// Decides which transactions are sampled for distributed tracing, aiming at `target` sampled
+/// transactions per `period`.
+#[derive(Debug)]
+pub(crate) struct AdaptiveSampler {
+    period: Duration,
+    target: u64,
+    state: Mutex<SamplerState>,
+}
+
+#[derive(Debug)]
+struct SamplerState {
+    num_sampled: u64,
+    num_seen: u64,
+    end: Instant,
+    /// The transactions with the priority below this are not sampled.
+    priority_min: f32,
+}
+
+impl AdaptiveSampler {
+    pub(crate) fn new(period: Duration, target: u64, now: Instant) -> Self {
+        Self {
+            period,
+            target,
+            state: Mutex::new(SamplerState {
+                num_sampled: 0,
+                num_seen: 0,
+                end: now + period,
+                // Sample the first `target` transactions of the first period.
+                priority_min: 0.0,
+            }),
+        }
+    }
+
+    pub(crate) fn compute_sampled(&self, priority: Priority, now: Instant) -> bool {
+        self.compute_sampled_with(priority, now, |num_seen| {
+            rand::thread_rng().gen_range(0..num_seen)
+        })
+    }
+
+    /// `random(n)` returns a random number in `0..n`.
+    fn compute_sampled_with(
+        &self,
+        priority: Priority,
+        now: Instant,
+        random: impl FnOnce(u64) -> u64,
+    ) -> bool {
+        let mut state = self.state.lock();
+        // Loop in case no transaction was seen during the whole last period.
+        while now > state.end {
+            state.priority_min = if state.num_seen == 0 {
+                0.0
+            } else {
+                1.0 - self.target as f32 / state.num_seen as f32
+            };
+            state.num_sampled = 0;
+            state.num_seen = 0;
+            state.end += self.period;
+        }
+        state.num_seen += 1;
+
+        let sampled = if state.num_sampled > self.target {
+            // Exponential backoff once the target is exceeded in the period
+            self.compute_sampled_backoff(random(state.num_seen), state.num_sampled)
+        } else {
+            priority.0 >= state.priority_min
+        };
+        if sampled {
+            state.num_sampled += 1;
+        }
+        sampled
+    }
+
+    fn compute_sampled_backoff(&self, random: u64, num_sampled: u64) -> bool {
+        let target = self.target as f64;
+        let threshold = target.powf(target / num_sampled as f64) - target.powf(0.5);
+        (random as f64) < threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_period() {
+        let now = Instant::now();
+        let sampler = AdaptiveSampler::new(Duration::from_secs(60), 10, now);
+        let num_sampled = (0..1000)
+            .filter(|_| sampler.compute_sampled_with(Priority(0.0), now, |_| 0))
+            .count();
+        // The backoff starts after exceeding the target, and stops when 10^(10/n) <= 10^0.5.
+        assert_eq!(num_sampled, 20);
+
+        let sampler = AdaptiveSampler::new(Duration::from_secs(60), 10, now);
+        let num_sampled = (0..1000)
+            .filter(|_| sampler.compute_sampled_with(Priority(0.0), now, |n| n - 1))
+            .count();
+        assert_eq!(num_sampled, 11);
+    }
+
+    #[test]
+    fn test_later_periods() {
+        let now = Instant::now();
+        let sampler = AdaptiveSampler::new(Duration::from_secs(60), 10, now);
+        for _ in 0..100 {
+            sampler.compute_sampled(Priority(0.5), now);
+        }
+        // The threshold is now 1 - 10 / 100.
+        let now = now + Duration::from_secs(61);
+        assert!(!sampler.compute_sampled(Priority(0.8), now));
+        assert!(sampler.compute_sampled(Priority(0.95), now));
+
+        // No transactions in the last period: sample everything again.
+        let now = now + Duration::from_secs(120);
+        assert!(sampler.compute_sampled(Priority(0.0), now));
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::payloads::analytics_events::Properties;
+use crate::priority::Priority;
+
+/// A reservoir of events keeping the ones with the highest priority.
+#[derive(Debug, Clone)]
+pub(crate) struct AnalyticsEvents<T> {
+    capacity: usize,
+    num_seen: usize,
+    events: BinaryHeap<Reverse<PrioritizedEvent<T>>>,
+}
+
+impl<T> AnalyticsEvents<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            num_seen: 0,
+            events: BinaryHeap::new(),
+        }
+    }
+
+    /// Creates an empty reservoir with the same capacity.
+    pub(crate) fn renew(&self) -> Self {
+        Self::new(self.capacity)
+    }
+
+    pub(crate) fn push(&mut self, event: T, priority: Priority) {
+        self.num_seen += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        let event = Reverse(PrioritizedEvent { priority, event });
+        if self.events.len() < self.capacity {
+            self.events.push(event);
+        } else if let Some(mut lowest) = self.events.peek_mut() {
+            if event < *lowest {
+                *lowest = event;
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub(crate) fn properties(&self) -> Properties {
+        Properties {
+            reservoir_size: self.capacity as i32,
+            events_seen: self.num_seen as i32,
+        }
+    }
+
+    pub(crate) fn into_events(self) -> Vec<T> {
+        self.events
+            .into_iter()
+            .map(|Reverse(event)| event.event)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PrioritizedEvent<T> {
+    priority: Priority,
+    event: T,
+}
+
+impl<T> PartialEq for PrioritizedEvent<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for PrioritizedEvent<T> {}
+
+impl<T> PartialOrd for PrioritizedEvent<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for PrioritizedEvent<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.total_cmp(&other.priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservoir_keeps_highest_priorities() {
+        let mut events = AnalyticsEvents::new(2);
+        for (i, &p) in [0.5, 0.1, 0.9, 0.3, 0.7].iter().enumerate() {
+            events.push(i, Priority(p));
+        }
+        let properties = events.properties();
+        assert_eq!(properties.reservoir_size, 2);
+        assert_eq!(properties.events_seen, 5);
+        let mut kept = events.into_events();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![2, 4]);
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let mut events = AnalyticsEvents::new(0);
+        events.push((), Priority(1.0));
+        assert!(events.is_empty());
+        assert_eq!(events.properties().events_seen, 1);
+    }
+}
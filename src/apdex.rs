@@ -20,9 +20,9 @@ pub(crate) enum ApdexZone {
 
 impl ApdexZone {
     pub(crate) fn calculate(threshold: Duration, duration: Duration) -> Self {
-        if duration < threshold {
+        if duration <= threshold {
             ApdexZone::Satisfying
-        } else if duration < apdex_failing_threshold(threshold) {
+        } else if duration <= apdex_failing_threshold(threshold) {
             ApdexZone::Tolerating
         } else {
             ApdexZone::Failing
@@ -77,3 +77,19 @@ impl<'de> Deserialize<'de> for ApdexZone {
             .map_err(|_| Error::invalid_value(Unexpected::Str(&s), &"a string S, T, or F"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calculate() {
+        let threshold = Duration::from_millis(500);
+        let zone = |millis| ApdexZone::calculate(threshold, Duration::from_millis(millis));
+        assert_eq!(zone(100), ApdexZone::Satisfying);
+        assert_eq!(zone(500), ApdexZone::Satisfying);
+        assert_eq!(zone(501), ApdexZone::Tolerating);
+        assert_eq!(zone(2000), ApdexZone::Tolerating);
+        assert_eq!(zone(2001), ApdexZone::Failing);
+    }
+}
@@ -2,11 +2,17 @@
 // Copyright 2020 Masaki Hara.
 
 use std::collections::HashMap;
-use std::time::Duration;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 
+use crate::adaptive_sampler::AdaptiveSampler;
 use crate::connect_reply::{ConnectReply, PreconnectReply};
 use crate::domain_defs::AgentRunId;
-use crate::limits::{DEFAULT_CONFIGURABLE_EVENT_HARVEST, FIXED_HARVEST_PERIOD};
+use crate::limits::{
+    DEFAULT_CONFIGURABLE_EVENT_HARVEST, FIXED_HARVEST_PERIOD, MAX_CUSTOM_EVENTS, MAX_ERROR_EVENTS,
+    MAX_SPAN_EVENTS, MAX_TXN_EVENTS, SAMPLING_TARGET_PERIOD,
+};
+use crate::rules::{MetricRules, SegmentTerms};
 
 #[derive(Debug)]
 pub(crate) struct AppRun {
@@ -16,15 +22,48 @@ pub(crate) struct AppRun {
 
     pub(crate) agent_run_id: AgentRunId,
     pub(crate) request_headers_map: HashMap<String, String>,
+    pub(crate) url_rules: MetricRules,
+    pub(crate) txn_name_rules: MetricRules,
+    pub(crate) metric_rules: MetricRules,
+    pub(crate) segment_terms: SegmentTerms,
     pub(crate) apdex_t: Duration,
+    /// Apdex thresholds of key transactions
+    pub(crate) key_txn_apdex: HashMap<String, Duration>,
     pub(crate) metrics_traces_period: Duration,
     pub(crate) span_events_period: Duration,
     pub(crate) custom_events_period: Duration,
     pub(crate) txn_events_period: Duration,
     pub(crate) error_events_period: Duration,
+    pub(crate) max_txn_events: usize,
+    pub(crate) max_error_events: usize,
+    pub(crate) max_custom_events: usize,
+    pub(crate) max_span_events: usize,
+    pub(crate) collect_errors: bool,
+    pub(crate) collect_error_events: bool,
+    pub(crate) collect_custom_events: bool,
+    pub(crate) collect_span_events: bool,
+    // Server-side overrides of the error collector config
+    pub(crate) error_collector_enabled: Option<bool>,
+    pub(crate) ignore_status_codes: Option<Vec<u16>>,
+    // Distributed tracing
+    pub(crate) account_id: String,
+    pub(crate) trusted_account_key: String,
+    pub(crate) primary_application_id: String,
+    pub(crate) sampler: AdaptiveSampler,
+    // Cross application tracing
+    pub(crate) encoding_key: String,
+    pub(crate) cross_process_id: String,
+    pub(crate) trusted_account_set: Vec<i32>,
 }
 
 impl AppRun {
+    pub(crate) fn apdex_threshold(&self, final_name: &str) -> Duration {
+        self.key_txn_apdex
+            .get(final_name)
+            .copied()
+            .unwrap_or(self.apdex_t)
+    }
+
     pub(crate) fn new(license: &str, reply_pre: &PreconnectReply, reply: &ConnectReply) -> AppRun {
         let configurable_period = if let Some(ms) = reply.event_harvest_config.report_period_ms {
             Duration::from_millis(u64::from(ms))
@@ -38,18 +77,33 @@ impl AppRun {
                 FIXED_HARVEST_PERIOD
             }
         };
-        let apdex_t = if reply.apdex_t >= 0.0 && reply.apdex_t < u64::MAX as f64 {
-            Duration::from_secs_f64(reply.apdex_t)
-        } else {
-            Duration::from_secs(1)
-        };
+        let harvest_limits = &reply.event_harvest_config.harvest_limits;
+        let apdex_t =
+            Duration::try_from_secs_f64(reply.apdex_t).unwrap_or_else(|_| Duration::from_secs(1));
+        let key_txn_apdex = reply
+            .web_transactions_apdex
+            .iter()
+            .filter_map(|(name, &apdex_t)| {
+                Some((name.clone(), Duration::try_from_secs_f64(apdex_t).ok()?))
+            })
+            .collect();
+        let agent_config = reply.agent_config.as_ref();
+        let sampling_period = u64::try_from(reply.sampling_target_period_in_seconds)
+            .ok()
+            .filter(|&secs| secs > 0)
+            .map_or(SAMPLING_TARGET_PERIOD, Duration::from_secs);
         Self {
             host: reply_pre.redirect_host.clone(),
             license: license.to_owned(),
 
             agent_run_id: reply.agent_run_id.clone(),
             request_headers_map: reply.request_headers_map.clone(),
+            url_rules: MetricRules::new(&reply.url_rules),
+            txn_name_rules: MetricRules::new(&reply.transaction_name_rules),
+            metric_rules: MetricRules::new(&reply.metric_name_rules),
+            segment_terms: SegmentTerms::new(&reply.transaction_segment_terms),
             apdex_t,
+            key_txn_apdex,
             metrics_traces_period: FIXED_HARVEST_PERIOD,
             span_events_period: select_period(
                 reply.event_harvest_config.harvest_limits.span_event_data,
@@ -66,6 +120,36 @@ impl AppRun {
             error_events_period: select_period(
                 reply.event_harvest_config.harvest_limits.error_event_data,
             ),
+            max_txn_events: harvest_limits.analytic_event_data.unwrap_or(MAX_TXN_EVENTS) as usize,
+            max_error_events: harvest_limits.error_event_data.unwrap_or(MAX_ERROR_EVENTS) as usize,
+            max_custom_events: harvest_limits
+                .custom_event_data
+                .unwrap_or(MAX_CUSTOM_EVENTS) as usize,
+            max_span_events: harvest_limits.span_event_data.unwrap_or(MAX_SPAN_EVENTS) as usize,
+            collect_errors: reply.collect_errors.unwrap_or(true),
+            collect_error_events: reply.collect_error_events.unwrap_or(true),
+            collect_custom_events: reply.collect_custom_events.unwrap_or(true),
+            collect_span_events: reply.collect_span_events.unwrap_or(true),
+            error_collector_enabled: agent_config.and_then(|c| c.error_collector_enabled),
+            ignore_status_codes: agent_config
+                .and_then(|c| c.error_collector_ignore_status_codes.as_ref())
+                .map(|codes| {
+                    codes
+                        .iter()
+                        .filter_map(|&code| u16::try_from(code).ok())
+                        .collect()
+                }),
+            account_id: reply.account_id.clone(),
+            trusted_account_key: if reply.trusted_account_key.is_empty() {
+                reply.account_id.clone()
+            } else {
+                reply.trusted_account_key.clone()
+            },
+            primary_application_id: reply.primary_application_id.clone(),
+            sampler: AdaptiveSampler::new(sampling_period, reply.sampling_target, Instant::now()),
+            encoding_key: reply.encoding_key.clone(),
+            cross_process_id: reply.cross_process_id.clone(),
+            trusted_account_set: reply.trusted_account_set.clone(),
         }
     }
 }
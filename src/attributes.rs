@@ -0,0 +1,412 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::config::{AttributeDestinationConfig, Config};
+use crate::payloads::{AgentAttrs, UserAttrs};
+
+const ATTRIBUTE_KEY_LENGTH_LIMIT: usize = 255;
+const ATTRIBUTE_VALUE_LENGTH_LIMIT: usize = 255;
+const CUSTOM_EVENT_ATTRIBUTE_LIMIT: usize = 64;
+const USER_ATTRIBUTE_LIMIT: usize = 64;
+const EVENT_TYPE_LENGTH_LIMIT: usize = 255;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum AttributeError {
+    #[error("event type exceeds length limit of {}", EVENT_TYPE_LENGTH_LIMIT)]
+    EventTypeLength,
+    #[error("event type must match [a-zA-Z0-9:_ ]+")]
+    EventTypeInvalid,
+    #[error(
+        "attribute key exceeds length limit of {}: {0}",
+        ATTRIBUTE_KEY_LENGTH_LIMIT
+    )]
+    KeyLength(String),
+    #[error("attribute value is not a finite number: {0}")]
+    NonFiniteValue(String),
+    #[error("attribute limit of {} exceeded", USER_ATTRIBUTE_LIMIT)]
+    TooManyAttributes,
+}
+
+/// A value of a user-defined attribute.
+///
+/// Strings longer than 255 bytes are truncated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+impl AttributeValue {
+    pub(crate) fn into_json(self) -> serde_json::Value {
+        match self {
+            AttributeValue::String(s) => truncate(s, ATTRIBUTE_VALUE_LENGTH_LIMIT).into(),
+            AttributeValue::Bool(b) => b.into(),
+            AttributeValue::Int(i) => i.into(),
+            AttributeValue::UInt(u) => u.into(),
+            AttributeValue::Float(f) => f.into(),
+        }
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(s: String) -> Self {
+        AttributeValue::String(s)
+    }
+}
+
+impl<'a> From<&'a str> for AttributeValue {
+    fn from(s: &'a str) -> Self {
+        AttributeValue::String(s.to_owned())
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(b: bool) -> Self {
+        AttributeValue::Bool(b)
+    }
+}
+
+macro_rules! impl_from_number {
+    ($variant:ident, $target:ty, $($t:ty),*) => {
+        $(
+            impl From<$t> for AttributeValue {
+                fn from(x: $t) -> Self {
+                    AttributeValue::$variant(<$target>::from(x))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_number!(Int, i64, i8, i16, i32, i64);
+impl_from_number!(UInt, u64, u8, u16, u32, u64);
+impl_from_number!(Float, f64, f32, f64);
+
+pub(crate) fn validate_event_type(event_type: &str) -> Result<(), AttributeError> {
+    if event_type.len() > EVENT_TYPE_LENGTH_LIMIT {
+        return Err(AttributeError::EventTypeLength);
+    }
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || c == ':' || c == '_' || c == ' ';
+    if event_type.is_empty() || !event_type.chars().all(valid_char) {
+        return Err(AttributeError::EventTypeInvalid);
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_attribute(key: &str, value: &AttributeValue) -> Result<(), AttributeError> {
+    if key.len() > ATTRIBUTE_KEY_LENGTH_LIMIT {
+        return Err(AttributeError::KeyLength(key.to_owned()));
+    }
+    if let AttributeValue::Float(f) = value {
+        if !f.is_finite() {
+            return Err(AttributeError::NonFiniteValue(key.to_owned()));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn custom_event_attributes<I, K, V>(attributes: I) -> Result<UserAttrs, AttributeError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: Into<AttributeValue>,
+{
+    let mut attrs = UserAttrs::default();
+    for (key, value) in attributes {
+        let key = key.as_ref();
+        let value = value.into();
+        validate_attribute(key, &value)?;
+        attrs.0.insert(key.to_owned(), value.into_json());
+        if attrs.0.len() > CUSTOM_EVENT_ATTRIBUTE_LIMIT {
+            return Err(AttributeError::TooManyAttributes);
+        }
+    }
+    Ok(attrs)
+}
+
+/// Adds a user attribute to a transaction, replacing the value of the same key.
+pub(crate) fn add_user_attribute(
+    attrs: &mut UserAttrs,
+    key: &str,
+    value: AttributeValue,
+) -> Result<(), AttributeError> {
+    validate_attribute(key, &value)?;
+    if attrs.0.len() >= USER_ATTRIBUTE_LIMIT && !attrs.0.contains_key(key) {
+        return Err(AttributeError::TooManyAttributes);
+    }
+    attrs.0.insert(key.to_owned(), value.into_json());
+    Ok(())
+}
+
+/// A set of the places where attributes are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Destinations(u8);
+
+impl Destinations {
+    pub(crate) const NONE: Self = Self(0);
+    pub(crate) const TXN_EVENT: Self = Self(1 << 0);
+    pub(crate) const ERROR: Self = Self(1 << 1);
+    pub(crate) const TXN_TRACE: Self = Self(1 << 2);
+    pub(crate) const BROWSER: Self = Self(1 << 3);
+    pub(crate) const SPAN: Self = Self(1 << 4);
+    pub(crate) const ALL: Self = Self(0b1_1111);
+
+    pub(crate) fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub(crate) fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub(crate) fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The destinations of agent attributes without configuration.
+fn agent_attribute_default_destinations(key: &str) -> Destinations {
+    match key {
+        "request.headers.referer" => Destinations::TXN_TRACE.union(Destinations::ERROR),
+        _ => Destinations::ALL.difference(Destinations::BROWSER),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct AttributeModifier {
+    /// The attribute name, or its prefix if `wildcard` is set.
+    pattern: String,
+    wildcard: bool,
+    include: Destinations,
+    exclude: Destinations,
+}
+
+impl AttributeModifier {
+    fn matches(&self, key: &str) -> bool {
+        if self.wildcard {
+            key.starts_with(&self.pattern)
+        } else {
+            key == self.pattern
+        }
+    }
+}
+
+/// Decides the destinations of attributes from [`AttributeDestinationConfig`]s.
+#[derive(Debug, Clone)]
+pub(crate) struct AttributeFilter {
+    disabled: Destinations,
+    /// Sorted from the least specific one.
+    modifiers: Vec<AttributeModifier>,
+}
+
+impl AttributeFilter {
+    pub(crate) fn new(config: &Config) -> Self {
+        let configs: [(Destinations, &AttributeDestinationConfig); 6] = [
+            (Destinations::ALL, &config.attributes),
+            (
+                Destinations::TXN_EVENT,
+                &config.transaction_events.attributes,
+            ),
+            (Destinations::ERROR, &config.error_collector.attributes),
+            (
+                Destinations::TXN_TRACE,
+                &config.transaction_tracer.attributes,
+            ),
+            (Destinations::BROWSER, &config.browser_monitoring.attributes),
+            (Destinations::SPAN, &config.span_events.attributes),
+        ];
+        let mut disabled = Destinations::NONE;
+        let mut modifiers = HashMap::<&str, AttributeModifier>::new();
+        for &(destinations, config) in &configs {
+            if !config.enabled {
+                disabled = disabled.union(destinations);
+            }
+            let rules = config
+                .include
+                .iter()
+                .map(|pattern| (pattern, true))
+                .chain(config.exclude.iter().map(|pattern| (pattern, false)));
+            for (pattern, include) in rules {
+                let modifier = modifiers.entry(pattern).or_insert_with(|| {
+                    let (pattern, wildcard) = if let Some(prefix) = pattern.strip_suffix('*') {
+                        (prefix, true)
+                    } else {
+                        (pattern.as_str(), false)
+                    };
+                    AttributeModifier {
+                        pattern: pattern.to_owned(),
+                        wildcard,
+                        include: Destinations::NONE,
+                        exclude: Destinations::NONE,
+                    }
+                });
+                if include {
+                    modifier.include = modifier.include.union(destinations);
+                } else {
+                    modifier.exclude = modifier.exclude.union(destinations);
+                }
+            }
+        }
+        let mut modifiers = modifiers.into_values().collect::<Vec<_>>();
+        modifiers.sort_by(|a, b| {
+            (!a.wildcard, a.pattern.len(), &a.pattern).cmp(&(
+                !b.wildcard,
+                b.pattern.len(),
+                &b.pattern,
+            ))
+        });
+        Self {
+            disabled,
+            modifiers,
+        }
+    }
+
+    fn destinations(&self, key: &str, default: Destinations) -> Destinations {
+        let mut destinations = default;
+        for modifier in self.modifiers.iter().filter(|m| m.matches(key)) {
+            destinations = destinations
+                .union(modifier.include)
+                .difference(modifier.exclude);
+        }
+        destinations.difference(self.disabled)
+    }
+
+    pub(crate) fn agent_attrs(&self, attrs: &AgentAttrs, destination: Destinations) -> AgentAttrs {
+        AgentAttrs(self.filter(&attrs.0, destination, agent_attribute_default_destinations))
+    }
+
+    pub(crate) fn user_attrs(&self, attrs: &UserAttrs, destination: Destinations) -> UserAttrs {
+        UserAttrs(self.filter(&attrs.0, destination, |_| Destinations::ALL))
+    }
+
+    fn filter(
+        &self,
+        attrs: &HashMap<String, serde_json::Value>,
+        destination: Destinations,
+        default: impl Fn(&str) -> Destinations,
+    ) -> HashMap<String, serde_json::Value> {
+        attrs
+            .iter()
+            .filter(|(key, _)| self.destinations(key, default(key)).contains(destination))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+fn truncate(mut s: String, limit: usize) -> String {
+    if s.len() > limit {
+        let mut len = limit;
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        s.truncate(len);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_event_type() {
+        assert_eq!(validate_event_type("Purchase"), Ok(()));
+        assert_eq!(validate_event_type("my_app:Signup Completed"), Ok(()));
+        assert_eq!(
+            validate_event_type(""),
+            Err(AttributeError::EventTypeInvalid)
+        );
+        assert_eq!(
+            validate_event_type("Purchase!"),
+            Err(AttributeError::EventTypeInvalid)
+        );
+        assert_eq!(
+            validate_event_type(&"a".repeat(256)),
+            Err(AttributeError::EventTypeLength)
+        );
+    }
+
+    #[test]
+    fn test_custom_event_attributes() {
+        let attrs = custom_event_attributes(vec![
+            ("plan", AttributeValue::from("pro")),
+            ("seats", AttributeValue::from(3)),
+            ("note", AttributeValue::from("あ".repeat(100))),
+        ])
+        .unwrap();
+        assert_eq!(attrs.0["plan"], "pro");
+        assert_eq!(attrs.0["seats"], 3);
+        assert_eq!(attrs.0["note"].as_str().unwrap().len(), 255);
+
+        let long_key = "k".repeat(256);
+        assert_eq!(
+            custom_event_attributes(vec![(&long_key, 1)]).unwrap_err(),
+            AttributeError::KeyLength(long_key.clone())
+        );
+        assert_eq!(
+            custom_event_attributes(vec![("amount", f64::NAN)]).unwrap_err(),
+            AttributeError::NonFiniteValue("amount".to_owned())
+        );
+        let many = (0..65).map(|i| (i.to_string(), i)).collect::<Vec<_>>();
+        assert_eq!(
+            custom_event_attributes(many).unwrap_err(),
+            AttributeError::TooManyAttributes
+        );
+    }
+
+    #[test]
+    fn test_attribute_filter() {
+        let mut config = Config::default();
+        config.attributes.exclude = vec!["request.headers.*".to_owned()];
+        config.attributes.include = vec!["request.headers.host".to_owned()];
+        config.transaction_events.attributes.exclude =
+            vec!["request.*".to_owned(), "tenant".to_owned()];
+        config.error_collector.attributes.include = vec!["request.headers.accept".to_owned()];
+        let filter = AttributeFilter::new(&config);
+
+        let dests = |key: &str| filter.destinations(key, agent_attribute_default_destinations(key));
+        assert!(dests("request.method").contains(Destinations::ERROR));
+        assert!(!dests("request.method").contains(Destinations::TXN_EVENT));
+        assert!(!dests("request.method").contains(Destinations::BROWSER));
+        assert!(!dests("request.headers.userAgent").contains(Destinations::TXN_TRACE));
+        assert!(dests("request.headers.accept").contains(Destinations::ERROR));
+        assert!(!dests("request.headers.accept").contains(Destinations::TXN_TRACE));
+        // The exact match is more specific than the wildcards.
+        assert!(dests("request.headers.host").contains(Destinations::TXN_TRACE));
+        assert!(dests("request.headers.host").contains(Destinations::TXN_EVENT));
+
+        let mut attrs = UserAttrs::default();
+        add_user_attribute(&mut attrs, "tenant", "acme".into()).unwrap();
+        add_user_attribute(&mut attrs, "plan", "pro".into()).unwrap();
+        let filtered = filter.user_attrs(&attrs, Destinations::TXN_EVENT);
+        assert_eq!(filtered.0.len(), 1);
+        assert!(filtered.0.contains_key("plan"));
+        assert_eq!(filter.user_attrs(&attrs, Destinations::ERROR).0.len(), 2);
+
+        config.attributes.enabled = false;
+        let filter = AttributeFilter::new(&config);
+        assert!(filter.user_attrs(&attrs, Destinations::ERROR).0.is_empty());
+    }
+
+    #[test]
+    fn test_add_user_attribute() {
+        let mut attrs = UserAttrs::default();
+        for i in 0..64 {
+            add_user_attribute(&mut attrs, &i.to_string(), i.into()).unwrap();
+        }
+        assert_eq!(
+            add_user_attribute(&mut attrs, "tenant", "acme".into()),
+            Err(AttributeError::TooManyAttributes)
+        );
+        add_user_attribute(&mut attrs, "0", "replaced".into()).unwrap();
+        assert_eq!(attrs.0["0"], "replaced");
+        assert_eq!(attrs.0.len(), 64);
+    }
+}
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use crate::config::{Config, TransactionTracerConfig, UtilizationConfig};
+use crate::config::{
+    Config, DistributedTracerConfig, ErrorCollectorConfig, TransactionTracerConfig,
+    UtilizationConfig,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -12,6 +15,8 @@ pub(super) struct Settings {
     labels: HashMap<String, String>,
     host_display_name: Option<String>,
     transaction_tracer: TransactionTracerSettings,
+    error_collector: ErrorCollectorSettings,
+    distributed_tracer: DistributedTracerSettings,
     utilization: UtilizationSettings,
     host: Option<String>,
     // Tell who we are
@@ -26,6 +31,8 @@ impl Settings {
             labels: config.labels.clone(),
             host_display_name: config.host_display_name.clone(),
             transaction_tracer: TransactionTracerSettings::new(&config.transaction_tracer),
+            error_collector: ErrorCollectorSettings::new(&config.error_collector),
+            distributed_tracer: DistributedTracerSettings::new(&config.distributed_tracer),
             utilization: UtilizationSettings::new(&config.utilization),
             host: config.host.clone(),
             unofficial_agent_repository: "https://github.com/qnighy/newrelic-unofficial-rust"
@@ -48,6 +55,42 @@ impl TransactionTracerSettings {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorCollectorSettings {
+    enabled: bool,
+    capture_events: bool,
+    ignore_status_codes: Vec<u16>,
+    expect_status_codes: Vec<u16>,
+}
+
+impl ErrorCollectorSettings {
+    fn new(config: &ErrorCollectorConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            capture_events: config.capture_events,
+            ignore_status_codes: config.ignore_status_codes.clone(),
+            expect_status_codes: config.expected_status_codes.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DistributedTracerSettings {
+    enabled: bool,
+    exclude_new_relic_header: bool,
+}
+
+impl DistributedTracerSettings {
+    fn new(config: &DistributedTracerConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            exclude_new_relic_header: config.exclude_newrelic_header,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct UtilizationSettings {
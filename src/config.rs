@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::Duration;
 use thiserror::Error;
 
 const LICENSE_LENGTH: usize = 40;
@@ -22,7 +23,15 @@ pub struct Config {
     pub enabled: bool,
     pub labels: HashMap<String, String>,
     pub host_display_name: Option<String>,
+    /// The attribute rules applied to all destinations.
+    pub attributes: AttributeDestinationConfig,
+    pub transaction_events: TransactionEventsConfig,
     pub transaction_tracer: TransactionTracerConfig,
+    pub error_collector: ErrorCollectorConfig,
+    pub span_events: SpanEventsConfig,
+    pub distributed_tracer: DistributedTracerConfig,
+    pub cross_application_tracer: CrossApplicationTracerConfig,
+    pub browser_monitoring: BrowserMonitoringConfig,
     pub utilization: UtilizationConfig,
     pub host: Option<String>,
     #[doc(hidden)]
@@ -37,7 +46,14 @@ impl Default for Config {
             enabled: true,
             labels: HashMap::default(),
             host_display_name: None,
+            attributes: AttributeDestinationConfig::default(),
+            transaction_events: TransactionEventsConfig::default(),
             transaction_tracer: TransactionTracerConfig::default(),
+            error_collector: ErrorCollectorConfig::default(),
+            span_events: SpanEventsConfig::default(),
+            distributed_tracer: DistributedTracerConfig::default(),
+            cross_application_tracer: CrossApplicationTracerConfig::default(),
+            browser_monitoring: BrowserMonitoringConfig::default(),
             utilization: UtilizationConfig::default(),
             host: None,
             __non_exhaustive: (),
@@ -115,9 +131,54 @@ impl Config {
     }
 }
 
+/// Controls which attributes are sent to a destination.
+///
+/// An attribute name in `include` or `exclude` may end with `*` to match any suffix.
+/// More specific rules take precedence, and `exclude` takes precedence over `include`.
+#[derive(Debug, Clone)]
+pub struct AttributeDestinationConfig {
+    pub enabled: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for AttributeDestinationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            include: vec![],
+            exclude: vec![],
+            __non_exhaustive: (),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionEventsConfig {
+    pub enabled: bool,
+    pub attributes: AttributeDestinationConfig,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for TransactionEventsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            attributes: AttributeDestinationConfig::default(),
+            __non_exhaustive: (),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TransactionTracerConfig {
     pub enabled: bool,
+    /// Segments shorter than this are omitted from transaction traces.
+    pub segment_threshold: Duration,
+    pub attributes: AttributeDestinationConfig,
     #[doc(hidden)]
     pub __non_exhaustive: (),
 }
@@ -126,6 +187,112 @@ impl Default for TransactionTracerConfig {
     fn default() -> Self {
         Self {
             enabled: true,
+            segment_threshold: Duration::from_millis(2),
+            attributes: AttributeDestinationConfig::default(),
+            __non_exhaustive: (),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorCollectorConfig {
+    pub enabled: bool,
+    /// Whether to send error events in addition to error traces.
+    pub capture_events: bool,
+    /// Errors with these status codes are not reported. Defaults to 404.
+    ///
+    /// Overridden by the server-side configuration.
+    pub ignore_status_codes: Vec<u16>,
+    /// Errors with these classes are not reported.
+    pub ignore_classes: Vec<String>,
+    /// Errors with the class are not reported if their messages contain any of the strings.
+    pub ignore_messages: HashMap<String, Vec<String>>,
+    /// Errors with these status codes are reported, but don't affect the error rate and Apdex.
+    pub expected_status_codes: Vec<u16>,
+    /// Errors with these classes are reported, but don't affect the error rate and Apdex.
+    pub expected_classes: Vec<String>,
+    pub attributes: AttributeDestinationConfig,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for ErrorCollectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capture_events: true,
+            ignore_status_codes: vec![404],
+            ignore_classes: vec![],
+            ignore_messages: HashMap::new(),
+            expected_status_codes: vec![],
+            expected_classes: vec![],
+            attributes: AttributeDestinationConfig::default(),
+            __non_exhaustive: (),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpanEventsConfig {
+    /// Span events are sent only if distributed tracing is also enabled.
+    pub enabled: bool,
+    pub attributes: AttributeDestinationConfig,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for SpanEventsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            attributes: AttributeDestinationConfig::default(),
+            __non_exhaustive: (),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DistributedTracerConfig {
+    /// Propagates the trace context headers. Disabled by default.
+    pub enabled: bool,
+    /// Stops sending the `newrelic` header used by the older agents along with the W3C headers.
+    pub exclude_newrelic_header: bool,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+#[derive(Debug, Clone)]
+pub struct CrossApplicationTracerConfig {
+    /// Enabled by default. It has no effect if distributed tracing is enabled.
+    pub enabled: bool,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for CrossApplicationTracerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            __non_exhaustive: (),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowserMonitoringConfig {
+    /// Disabled by default.
+    pub attributes: AttributeDestinationConfig,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl Default for BrowserMonitoringConfig {
+    fn default() -> Self {
+        Self {
+            attributes: AttributeDestinationConfig {
+                enabled: false,
+                ..AttributeDestinationConfig::default()
+            },
             __non_exhaustive: (),
         }
     }
@@ -21,7 +21,8 @@ pub(crate) struct ConnectReply {
     pub(crate) entity_guid: String,
 
     // Transaction Name Modifiers
-    // transaction_segment_terms: SegmentRules,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) transaction_segment_terms: Vec<SegmentRule>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub(crate) transaction_name_rules: Vec<MetricRule>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -89,6 +90,12 @@ pub(crate) struct MetricRule {
     pub(crate) match_expression: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SegmentRule {
+    pub(crate) prefix: String,
+    pub(crate) terms: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Message {
     pub(crate) message: String,
@@ -107,8 +114,8 @@ pub(crate) struct ServerSideConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) error_collector_enabled: Option<bool>,
     #[serde(rename = "error_collector.ignore_status_codes")]
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub(crate) error_collector_ignore_status_codes: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error_collector_ignore_status_codes: Option<Vec<i32>>,
     #[serde(rename = "cross_application_tracer.enabled")]
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) cross_application_tracer_enabled: Option<bool>,
@@ -0,0 +1,367 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+//! Cross application tracing, the predecessor of distributed tracing.
+
+use http::header::{HeaderMap, HeaderValue, CONTENT_LENGTH};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::app_run::AppRun;
+use crate::config::Config;
+
+pub(crate) const NEWRELIC_ID_HEADER: &str = "X-NewRelic-ID";
+pub(crate) const NEWRELIC_TXN_HEADER: &str = "X-NewRelic-Transaction";
+pub(crate) const NEWRELIC_APP_DATA_HEADER: &str = "X-NewRelic-App-Data";
+
+/// Cross application tracing is used only if distributed tracing is disabled.
+pub(crate) fn enabled(config: &Config) -> bool {
+    config.cross_application_tracer.enabled && !config.distributed_tracer.enabled
+}
+
+/// The first of the `;`-separated application names.
+pub(crate) fn primary_app_name(app_name: &str) -> &str {
+    app_name.split(';').next().unwrap_or_default()
+}
+
+/// The cross application tracing state of a transaction.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TxnCrossProcess {
+    /// The `<account>#<app>` id of the trusted caller.
+    pub(crate) client_id: Option<String>,
+    pub(crate) referring_txn_guid: Option<String>,
+    pub(crate) referring_path_hash: Option<String>,
+    inbound_trip_id: Option<String>,
+    /// The path hashes sent to the callees.
+    path_hashes: BTreeSet<String>,
+}
+
+impl TxnCrossProcess {
+    /// Whether the transaction has been called by or called another application.
+    pub(crate) fn used(&self) -> bool {
+        self.client_id.is_some() || !self.path_hashes.is_empty()
+    }
+
+    /// Reads the `X-NewRelic-ID` and `X-NewRelic-Transaction` headers of an incoming request.
+    pub(crate) fn accept_request(&mut self, headers: &HeaderMap, run: &AppRun) {
+        let client_id = if let Some(id) = header_str(headers, NEWRELIC_ID_HEADER) {
+            id
+        } else {
+            return;
+        };
+        let client_id = if let Some(client_id) = deobfuscate_str(client_id, &run.encoding_key) {
+            client_id
+        } else {
+            log::debug!("invalid {} header", NEWRELIC_ID_HEADER);
+            return;
+        };
+        if !is_trusted(&client_id, run) {
+            log::debug!("untrusted cross process id: {}", client_id);
+            return;
+        }
+        self.client_id = Some(client_id);
+
+        let txn_data = header_str(headers, NEWRELIC_TXN_HEADER)
+            .and_then(|data| deobfuscate_str(data, &run.encoding_key))
+            .and_then(|data| serde_json::from_str::<Vec<Value>>(&data).ok());
+        if let Some(txn_data) = txn_data {
+            // [guid, unused, trip_id, path_hash]
+            let field = |i: usize| txn_data.get(i).and_then(Value::as_str).map(str::to_owned);
+            self.referring_txn_guid = field(0);
+            self.inbound_trip_id = field(2);
+            self.referring_path_hash = field(3);
+        }
+    }
+
+    /// Writes the `X-NewRelic-ID` and `X-NewRelic-Transaction` headers of an outgoing request.
+    pub(crate) fn insert_request_headers(
+        &mut self,
+        headers: &mut HeaderMap,
+        run: &AppRun,
+        guid: &str,
+        path_hash: String,
+    ) {
+        if run.encoding_key.is_empty() || run.cross_process_id.is_empty() {
+            return;
+        }
+        let txn_data = serde_json::json!([guid, false, self.trip_id(guid), path_hash]);
+        insert_header(
+            headers,
+            NEWRELIC_ID_HEADER,
+            obfuscate(run.cross_process_id.as_bytes(), &run.encoding_key),
+        );
+        insert_header(
+            headers,
+            NEWRELIC_TXN_HEADER,
+            obfuscate(txn_data.to_string().as_bytes(), &run.encoding_key),
+        );
+        self.path_hashes.insert(path_hash);
+    }
+
+    /// The trip id is inherited from the first transaction of the trip.
+    pub(crate) fn trip_id<'a>(&'a self, guid: &'a str) -> &'a str {
+        self.inbound_trip_id.as_deref().unwrap_or(guid)
+    }
+
+    pub(crate) fn path_hash(&self, txn_name: &str, app_name: &str) -> String {
+        path_hash(self.referring_path_hash.as_deref(), txn_name, app_name)
+    }
+
+    /// The path hashes sent to the callees other than the final one, joined with commas.
+    pub(crate) fn alternate_path_hashes(&self, path_hash: &str) -> Option<String> {
+        let hashes = self
+            .path_hashes
+            .iter()
+            .filter(|&hash| hash != path_hash)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if hashes.is_empty() {
+            None
+        } else {
+            Some(hashes.join(","))
+        }
+    }
+}
+
+/// The `X-NewRelic-App-Data` header sent back by the callee.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AppData {
+    pub(crate) cross_process_id: String,
+    pub(crate) transaction_name: String,
+    pub(crate) transaction_guid: Option<String>,
+}
+
+/// Writes the `X-NewRelic-App-Data` header of the response to a trusted caller.
+pub(crate) fn insert_app_data_header(
+    headers: &mut HeaderMap,
+    run: &AppRun,
+    txn_name: &str,
+    queue_duration: Duration,
+    response_duration: Duration,
+    guid: &str,
+) {
+    if run.encoding_key.is_empty() || run.cross_process_id.is_empty() {
+        return;
+    }
+    let content_length = header_str(headers, CONTENT_LENGTH.as_str())
+        .and_then(|len| len.parse::<i64>().ok())
+        .unwrap_or(-1);
+    let app_data = serde_json::json!([
+        run.cross_process_id,
+        txn_name,
+        queue_duration.as_secs_f64(),
+        response_duration.as_secs_f64(),
+        content_length,
+        guid,
+        false,
+    ]);
+    insert_header(
+        headers,
+        NEWRELIC_APP_DATA_HEADER,
+        obfuscate(app_data.to_string().as_bytes(), &run.encoding_key),
+    );
+}
+
+/// Reads the `X-NewRelic-App-Data` header. Returns `None` if it is invalid or not trusted.
+pub(crate) fn accept_app_data(value: &str, run: &AppRun) -> Option<AppData> {
+    let app_data = deobfuscate_str(value, &run.encoding_key)?;
+    // [cross_process_id, transaction_name, queue_time, response_time, content_length, guid, unused]
+    let app_data = serde_json::from_str::<Vec<Value>>(&app_data).ok()?;
+    if app_data.len() < 5 {
+        return None;
+    }
+    let cross_process_id = app_data[0].as_str()?.to_owned();
+    if !is_trusted(&cross_process_id, run) {
+        log::debug!("untrusted cross process id: {}", cross_process_id);
+        return None;
+    }
+    Some(AppData {
+        cross_process_id,
+        transaction_name: app_data[1].as_str()?.to_owned(),
+        transaction_guid: app_data.get(5).and_then(Value::as_str).map(str::to_owned),
+    })
+}
+
+/// The cross process id is `<account>#<app>`.
+fn is_trusted(cross_process_id: &str, run: &AppRun) -> bool {
+    let account = cross_process_id.split('#').next().unwrap_or_default();
+    account
+        .parse::<i32>()
+        .is_ok_and(|account| run.trusted_account_set.contains(&account))
+}
+
+fn path_hash(referring_path_hash: Option<&str>, txn_name: &str, app_name: &str) -> String {
+    let referring = referring_path_hash
+        .and_then(|hash| u32::from_str_radix(hash, 16).ok())
+        .unwrap_or(0);
+    let digest = md5::compute(format!("{};{}", app_name, txn_name));
+    let hash = u32::from_be_bytes([digest[12], digest[13], digest[14], digest[15]]);
+    format!("{:08x}", referring.rotate_left(1) ^ hash)
+}
+
+fn obfuscate(input: &[u8], key: &str) -> String {
+    base64::encode(xor(input, key.as_bytes()))
+}
+
+fn deobfuscate_str(input: &str, key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+    let decoded = base64::decode(input.trim()).ok()?;
+    String::from_utf8(xor(&decoded, key.as_bytes())).ok()
+}
+
+fn xor(input: &[u8], key: &[u8]) -> Vec<u8> {
+    input
+        .iter()
+        .zip(key.iter().cycle())
+        .map(|(&b, &k)| b ^ k)
+        .collect()
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+fn insert_header(headers: &mut HeaderMap, name: &'static str, value: String) {
+    // Base64 is always a valid header value.
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::connect_reply::{ConnectReply, PreconnectReply};
+
+    pub(crate) fn test_run() -> AppRun {
+        let reply = serde_json::from_value::<ConnectReply>(serde_json::json!({
+            "agent_run_id": "run",
+            "request_headers_map": {},
+            "entity_guid": "",
+            "encoding_key": "d67afc830dab717fd163bfcb0b8b88423e9a1a3b",
+            "cross_process_id": "1#1",
+            "trusted_account_set": [1, 2],
+            "apdex_t": 0.5,
+            "js_agent_loader": "",
+            "beacon": "",
+            "browser_key": "",
+            "application_id": "",
+            "error_beacon": "",
+            "js_agent_file": "",
+            "messages": [],
+            "account_id": "1",
+            "trusted_account_key": "1",
+            "primary_application_id": "1",
+            "sampling_target": 10,
+            "sampling_target_period_in_seconds": 60,
+            "event_harvest_config": { "harvest_limits": {} },
+        }))
+        .unwrap();
+        let reply_pre = PreconnectReply {
+            redirect_host: "collector.newrelic.com".to_owned(),
+        };
+        AppRun::new("license", &reply_pre, &reply)
+    }
+
+    #[test]
+    fn test_obfuscate() {
+        let key = "d67afc830dab717fd163bfcb0b8b88423e9a1a3b";
+        let obfuscated = obfuscate(b"1#1", key);
+        assert_eq!(obfuscated, "VRUG");
+        assert_eq!(deobfuscate_str(&obfuscated, key).as_deref(), Some("1#1"));
+        assert_eq!(deobfuscate_str(&obfuscated, ""), None);
+        assert_eq!(deobfuscate_str("!", key), None);
+    }
+
+    #[test]
+    fn test_path_hash() {
+        assert_eq!(
+            path_hash(None, "transaction A", "application A"),
+            "5e17050e"
+        );
+        assert_eq!(path_hash(Some("00000000"), "", ""), "e1f4fbf1");
+        assert_eq!(
+            path_hash(Some("12345678"), "WebTransaction/Go/hello", "app"),
+            "0e99579e"
+        );
+        // The referring hash is rotated, not shifted.
+        assert_eq!(
+            path_hash(Some("b8e33f8c"), "transaction B", "application B"),
+            "d8861c36"
+        );
+        // Invalid referring hashes are treated as zero.
+        assert_eq!(
+            path_hash(Some("xyz"), "transaction A", "application A"),
+            "5e17050e"
+        );
+    }
+
+    #[test]
+    fn test_alternate_path_hashes() {
+        let mut cross_process = TxnCrossProcess::default();
+        assert!(!cross_process.used());
+        assert_eq!(cross_process.trip_id("guid"), "guid");
+        cross_process.path_hashes.insert("b".to_owned());
+        cross_process.path_hashes.insert("a".to_owned());
+        cross_process.path_hashes.insert("c".to_owned());
+        assert!(cross_process.used());
+        assert_eq!(
+            cross_process.alternate_path_hashes("c").as_deref(),
+            Some("a,b")
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let run = test_run();
+        let mut caller = TxnCrossProcess::default();
+        let mut headers = HeaderMap::new();
+        let path_hash = caller.path_hash("WebTransaction/Go/caller", "app");
+        caller.insert_request_headers(&mut headers, &run, "1111", path_hash.clone());
+        assert!(caller.used());
+
+        let mut callee = TxnCrossProcess::default();
+        callee.accept_request(&headers, &run);
+        assert_eq!(callee.client_id.as_deref(), Some("1#1"));
+        assert_eq!(callee.referring_txn_guid.as_deref(), Some("1111"));
+        assert_eq!(callee.referring_path_hash.as_deref(), Some(&*path_hash));
+        assert_eq!(callee.trip_id("2222"), "1111");
+
+        let mut response = HeaderMap::new();
+        response.insert(CONTENT_LENGTH, HeaderValue::from_static("42"));
+        insert_app_data_header(
+            &mut response,
+            &run,
+            "WebTransaction/Go/callee",
+            Duration::from_millis(10),
+            Duration::from_millis(100),
+            "2222",
+        );
+        let app_data = response[NEWRELIC_APP_DATA_HEADER].to_str().unwrap();
+        assert_eq!(
+            accept_app_data(app_data, &run),
+            Some(AppData {
+                cross_process_id: "1#1".to_owned(),
+                transaction_name: "WebTransaction/Go/callee".to_owned(),
+                transaction_guid: Some("2222".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_untrusted() {
+        let run = test_run();
+        let mut headers = HeaderMap::new();
+        insert_header(
+            &mut headers,
+            NEWRELIC_ID_HEADER,
+            obfuscate(b"3#1", &run.encoding_key),
+        );
+        let mut callee = TxnCrossProcess::default();
+        callee.accept_request(&headers, &run);
+        assert!(!callee.used());
+    }
+}
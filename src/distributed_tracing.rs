@@ -0,0 +1,582 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use http::header::{HeaderMap, HeaderValue};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::priority::Priority;
+
+pub(crate) const TRACEPARENT_HEADER: &str = "traceparent";
+pub(crate) const TRACESTATE_HEADER: &str = "tracestate";
+pub(crate) const NEWRELIC_HEADER: &str = "newrelic";
+
+const W3C_VERSION: &str = "00";
+const TRACE_ID_LENGTH: usize = 32;
+const SPAN_ID_LENGTH: usize = 16;
+const NR_TRACESTATE_VERSION: &str = "0";
+const MAX_TRACESTATE_ENTRIES: usize = 32;
+const NEWRELIC_PAYLOAD_VERSION: [u32; 2] = [0, 1];
+
+pub(crate) const TRANSPORT_HTTP: &str = "HTTP";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ParentType {
+    App,
+    Browser,
+    Mobile,
+}
+
+impl ParentType {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "0" => Some(ParentType::App),
+            "1" => Some(ParentType::Browser),
+            "2" => Some(ParentType::Mobile),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "App" => Some(ParentType::App),
+            "Browser" => Some(ParentType::Browser),
+            "Mobile" => Some(ParentType::Mobile),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            ParentType::App => "0",
+            ParentType::Browser => "1",
+            ParentType::Mobile => "2",
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ParentType::App => "App",
+            ParentType::Browser => "Browser",
+            ParentType::Mobile => "Mobile",
+        }
+    }
+}
+
+/// The trace context received from the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct InboundPayload {
+    pub(crate) trace_id: String,
+    /// The span id of the caller.
+    pub(crate) parent_span_id: Option<String>,
+    /// The New Relic entry of the trusted account in `tracestate`, if any.
+    pub(crate) nr: Option<NrTraceState>,
+    /// The other entries of `tracestate`, passed through to the callees.
+    pub(crate) other_entries: Vec<String>,
+    pub(crate) transport_type: &'static str,
+}
+
+/// The New Relic entry of `tracestate`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NrTraceState {
+    pub(crate) parent_type: ParentType,
+    pub(crate) account: String,
+    pub(crate) app: String,
+    pub(crate) span_id: Option<String>,
+    pub(crate) transaction_id: Option<String>,
+    pub(crate) sampled: Option<bool>,
+    pub(crate) priority: Option<Priority>,
+    pub(crate) timestamp: SystemTime,
+}
+
+impl InboundPayload {
+    /// The caller as used in the `DurationByCaller` metrics:
+    /// `<type>/<account>/<app>/<transport>`.
+    pub(crate) fn caller(&self) -> String {
+        if let Some(nr) = &self.nr {
+            format!(
+                "{}/{}/{}/{}",
+                nr.parent_type.as_str(),
+                nr.account,
+                nr.app,
+                self.transport_type
+            )
+        } else {
+            UNKNOWN_CALLER.to_owned()
+        }
+    }
+
+    pub(crate) fn transport_duration(&self, txn_start: SystemTime) -> Option<Duration> {
+        let nr = self.nr.as_ref()?;
+        Some(txn_start.duration_since(nr.timestamp).unwrap_or_default())
+    }
+}
+
+pub(crate) const UNKNOWN_CALLER: &str = "Unknown/Unknown/Unknown/Unknown";
+
+/// The trace context sent to the callee.
+#[derive(Debug, Clone)]
+pub(crate) struct OutboundPayload<'a> {
+    pub(crate) trace_id: &'a str,
+    pub(crate) span_id: &'a str,
+    pub(crate) transaction_id: &'a str,
+    pub(crate) sampled: bool,
+    pub(crate) priority: Priority,
+    pub(crate) account: &'a str,
+    pub(crate) app: &'a str,
+    pub(crate) trusted_account_key: &'a str,
+    pub(crate) other_entries: &'a [String],
+    pub(crate) timestamp: SystemTime,
+}
+
+pub(crate) fn new_trace_id() -> String {
+    random_hex(TRACE_ID_LENGTH / 2)
+}
+
+/// Generates an id for transactions and spans.
+pub(crate) fn new_guid() -> String {
+    random_hex(SPAN_ID_LENGTH / 2)
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..bytes)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Parses `traceparent` into the trace id and the parent span id.
+fn parse_traceparent(value: &str) -> Option<(String, String)> {
+    let value = value.trim();
+    let mut parts = value.split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    if !is_hex(version, 2) || version == "ff" {
+        return None;
+    }
+    // Later versions may append fields.
+    if version == W3C_VERSION && parts.next().is_some() {
+        return None;
+    }
+    if !is_hex(trace_id, TRACE_ID_LENGTH) || trace_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    if !is_hex(parent_id, SPAN_ID_LENGTH) || parent_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    if !is_hex(flags, 2) {
+        return None;
+    }
+    Some((
+        trace_id.to_ascii_lowercase(),
+        parent_id.to_ascii_lowercase(),
+    ))
+}
+
+/// Parses the value of the New Relic `tracestate` entry:
+/// `<version>-<parent type>-<account>-<app>-<span id>-<transaction id>-<sampled>-<priority>-<timestamp>`.
+fn parse_nr_tracestate(value: &str) -> Option<NrTraceState> {
+    let fields = value.split('-').collect::<Vec<_>>();
+    if fields.len() < 9 || fields[0] != NR_TRACESTATE_VERSION {
+        return None;
+    }
+    let non_empty = |s: &str| Some(s.to_owned()).filter(|s| !s.is_empty());
+    let parent_type = ParentType::from_code(fields[1])?;
+    let account = non_empty(fields[2])?;
+    let app = non_empty(fields[3])?;
+    let sampled = match fields[6] {
+        "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    };
+    let priority = fields[7].parse::<f32>().ok().map(Priority);
+    let timestamp = UNIX_EPOCH + Duration::from_millis(fields[8].parse::<u64>().ok()?);
+    Some(NrTraceState {
+        parent_type,
+        account,
+        app,
+        span_id: non_empty(fields[4]),
+        transaction_id: non_empty(fields[5]),
+        sampled,
+        priority,
+        timestamp,
+    })
+}
+
+/// Reads the W3C trace context headers. Returns `None` if `traceparent` is missing or invalid.
+pub(crate) fn accept_w3c(
+    headers: &HeaderMap,
+    trusted_account_key: &str,
+    transport_type: &'static str,
+) -> Option<InboundPayload> {
+    let mut traceparents = headers.get_all(TRACEPARENT_HEADER).iter();
+    let traceparent = traceparents.next()?.to_str().ok()?;
+    if traceparents.next().is_some() {
+        return None;
+    }
+    let (trace_id, parent_span_id) = parse_traceparent(traceparent)?;
+
+    let nr_key = format!("{}@nr", trusted_account_key);
+    let mut nr = None;
+    let mut other_entries = vec![];
+    let entries = headers
+        .get_all(TRACESTATE_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty());
+    for entry in entries {
+        let (key, value) = if let Some(pair) = split_entry(entry) {
+            pair
+        } else {
+            continue;
+        };
+        if key == nr_key {
+            nr = parse_nr_tracestate(value);
+        } else if other_entries.len() < MAX_TRACESTATE_ENTRIES - 1 {
+            other_entries.push(entry.to_owned());
+        }
+    }
+    Some(InboundPayload {
+        trace_id,
+        parent_span_id: Some(parent_span_id),
+        nr,
+        other_entries,
+        transport_type,
+    })
+}
+
+fn split_entry(entry: &str) -> Option<(&str, &str)> {
+    let pos = entry.find('=')?;
+    Some((&entry[..pos], &entry[pos + 1..]))
+}
+
+/// Writes the W3C trace context headers.
+pub(crate) fn insert_w3c(headers: &mut HeaderMap, payload: &OutboundPayload<'_>) {
+    let traceparent = format!(
+        "{}-{:0>width$}-{}-{}",
+        W3C_VERSION,
+        payload.trace_id.to_ascii_lowercase(),
+        payload.span_id,
+        if payload.sampled { "01" } else { "00" },
+        width = TRACE_ID_LENGTH,
+    );
+    let timestamp = payload
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut tracestate = format!(
+        "{}@nr={}-{}-{}-{}-{}-{}-{}-{}-{}",
+        payload.trusted_account_key,
+        NR_TRACESTATE_VERSION,
+        ParentType::App.code(),
+        payload.account,
+        payload.app,
+        payload.span_id,
+        payload.transaction_id,
+        if payload.sampled { "1" } else { "0" },
+        payload.priority.0,
+        timestamp,
+    );
+    for entry in payload.other_entries {
+        tracestate.push(',');
+        tracestate.push_str(entry);
+    }
+    if let Ok(value) = HeaderValue::from_str(&traceparent) {
+        headers.insert(TRACEPARENT_HEADER, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&tracestate) {
+        headers.insert(TRACESTATE_HEADER, value);
+    }
+}
+
+/// The payload of the `newrelic` header used by the agents before W3C Trace Context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewRelicPayload {
+    v: [u32; 2],
+    d: NewRelicPayloadData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NewRelicPayloadData {
+    #[serde(rename = "ty")]
+    parent_type: String,
+    #[serde(rename = "ac")]
+    account: String,
+    #[serde(rename = "ap")]
+    app: String,
+    #[serde(rename = "id")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    span_id: Option<String>,
+    #[serde(rename = "tx")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    transaction_id: Option<String>,
+    #[serde(rename = "tr")]
+    trace_id: String,
+    #[serde(rename = "pr")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<Priority>,
+    #[serde(rename = "sa")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sampled: Option<bool>,
+    #[serde(rename = "ti")]
+    timestamp: u64,
+    /// Omitted if it is the same as the account.
+    #[serde(rename = "tk")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    trusted_account_key: Option<String>,
+}
+
+/// Reads the base64-encoded `newrelic` header. Returns `None` if the header is missing or invalid,
+/// or the caller is not trusted.
+pub(crate) fn accept_newrelic(
+    headers: &HeaderMap,
+    trusted_account_key: &str,
+    transport_type: &'static str,
+) -> Option<InboundPayload> {
+    let value = headers.get(NEWRELIC_HEADER)?.to_str().ok()?.trim();
+    let payload = if value.starts_with('{') {
+        // Some agents send the JSON without encoding.
+        serde_json::from_str::<NewRelicPayload>(value)
+    } else {
+        let decoded = base64::decode(value).ok()?;
+        serde_json::from_slice::<NewRelicPayload>(&decoded)
+    };
+    let payload = match payload {
+        Ok(payload) => payload,
+        Err(e) => {
+            log::debug!("invalid newrelic header: {}", e);
+            return None;
+        }
+    };
+    if payload.v[0] > NEWRELIC_PAYLOAD_VERSION[0] {
+        log::debug!("unsupported newrelic header version: {:?}", payload.v);
+        return None;
+    }
+    let data = payload.d;
+    let payload_key = data.trusted_account_key.as_ref().unwrap_or(&data.account);
+    if payload_key != trusted_account_key {
+        log::debug!("untrusted account in newrelic header: {}", payload_key);
+        return None;
+    }
+    if data.span_id.is_none() && data.transaction_id.is_none() {
+        return None;
+    }
+    Some(InboundPayload {
+        trace_id: data.trace_id,
+        parent_span_id: data.span_id.clone(),
+        nr: Some(NrTraceState {
+            parent_type: ParentType::from_name(&data.parent_type)?,
+            account: data.account,
+            app: data.app,
+            span_id: data.span_id,
+            transaction_id: data.transaction_id,
+            sampled: data.sampled,
+            priority: data.priority,
+            timestamp: UNIX_EPOCH + Duration::from_millis(data.timestamp),
+        }),
+        other_entries: vec![],
+        transport_type,
+    })
+}
+
+/// Writes the base64-encoded `newrelic` header.
+pub(crate) fn insert_newrelic(headers: &mut HeaderMap, payload: &OutboundPayload<'_>) {
+    let payload = NewRelicPayload {
+        v: NEWRELIC_PAYLOAD_VERSION,
+        d: NewRelicPayloadData {
+            parent_type: ParentType::App.as_str().to_owned(),
+            account: payload.account.to_owned(),
+            app: payload.app.to_owned(),
+            span_id: Some(payload.span_id.to_owned()),
+            transaction_id: Some(payload.transaction_id.to_owned()),
+            trace_id: payload.trace_id.to_owned(),
+            priority: Some(payload.priority),
+            sampled: Some(payload.sampled),
+            timestamp: payload
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            trusted_account_key: Some(payload.trusted_account_key.to_owned())
+                .filter(|key| key != payload.account),
+        },
+    };
+    let json = match serde_json::to_string(&payload) {
+        Ok(json) => json,
+        Err(e) => {
+            log::warn!("failed to encode newrelic header: {}", e);
+            return;
+        }
+    };
+    if let Ok(value) = HeaderValue::from_str(&base64::encode(json)) {
+        headers.insert(NEWRELIC_HEADER, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some((
+                "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                "00f067aa0ba902b7".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_traceparent("cc-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-what"),
+            Some((
+                "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                "00f067aa0ba902b7".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-what"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902-01"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_accept_w3c() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT_HEADER,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        headers.insert(
+            TRACESTATE_HEADER,
+            HeaderValue::from_static(
+                "rojo=00f067aa0ba902b7, 33@nr=0-0-33-5043-27ddd2d8890283b4-5569065a5b1313bd-1-1.23456-1518469636025",
+            ),
+        );
+        let payload = accept_w3c(&headers, "33", TRANSPORT_HTTP).unwrap();
+        assert_eq!(payload.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(payload.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(payload.other_entries, vec!["rojo=00f067aa0ba902b7"]);
+        assert_eq!(payload.caller(), "App/33/5043/HTTP");
+        let nr = payload.nr.unwrap();
+        assert_eq!(nr.span_id.as_deref(), Some("27ddd2d8890283b4"));
+        assert_eq!(nr.transaction_id.as_deref(), Some("5569065a5b1313bd"));
+        assert_eq!(nr.sampled, Some(true));
+        assert_eq!(nr.priority, Some(Priority(1.23456)));
+        assert_eq!(
+            nr.timestamp,
+            UNIX_EPOCH + Duration::from_millis(1_518_469_636_025)
+        );
+
+        // The entry of an untrusted account is passed through.
+        let payload = accept_w3c(&headers, "44", TRANSPORT_HTTP).unwrap();
+        assert!(payload.nr.is_none());
+        assert_eq!(payload.other_entries.len(), 2);
+        assert_eq!(payload.caller(), UNKNOWN_CALLER);
+    }
+
+    #[test]
+    fn test_insert_w3c() {
+        let mut headers = HeaderMap::new();
+        let other_entries = vec!["rojo=00f067aa0ba902b7".to_owned()];
+        insert_w3c(
+            &mut headers,
+            &OutboundPayload {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736",
+                span_id: "27ddd2d8890283b4",
+                transaction_id: "5569065a5b1313bd",
+                sampled: true,
+                priority: Priority(1.5),
+                account: "33",
+                app: "5043",
+                trusted_account_key: "33",
+                other_entries: &other_entries,
+                timestamp: UNIX_EPOCH + Duration::from_millis(1_518_469_636_025),
+            },
+        );
+        assert_eq!(
+            headers[TRACEPARENT_HEADER],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-27ddd2d8890283b4-01"
+        );
+        assert_eq!(
+            headers[TRACESTATE_HEADER],
+            "33@nr=0-0-33-5043-27ddd2d8890283b4-5569065a5b1313bd-1-1.5-1518469636025,rojo=00f067aa0ba902b7"
+        );
+    }
+
+    #[test]
+    fn test_newrelic_header() {
+        let mut headers = HeaderMap::new();
+        let other_entries = vec![];
+        let outbound = OutboundPayload {
+            trace_id: "d6b4ba0c3a712ca1",
+            span_id: "27ddd2d8890283b4",
+            transaction_id: "5569065a5b1313bd",
+            sampled: true,
+            priority: Priority(1.5),
+            account: "33",
+            app: "5043",
+            trusted_account_key: "1",
+            other_entries: &other_entries,
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_518_469_636_025),
+        };
+        insert_newrelic(&mut headers, &outbound);
+        insert_w3c(&mut headers, &outbound);
+        assert_eq!(
+            headers[TRACEPARENT_HEADER],
+            "00-0000000000000000d6b4ba0c3a712ca1-27ddd2d8890283b4-01"
+        );
+        let decoded = base64::decode(headers[NEWRELIC_HEADER].as_bytes()).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&decoded).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "v": [0, 1],
+                "d": {
+                    "ty": "App",
+                    "ac": "33",
+                    "ap": "5043",
+                    "id": "27ddd2d8890283b4",
+                    "tx": "5569065a5b1313bd",
+                    "tr": "d6b4ba0c3a712ca1",
+                    "pr": 1.5,
+                    "sa": true,
+                    "ti": 1_518_469_636_025u64,
+                    "tk": "1",
+                },
+            })
+        );
+
+        let payload = accept_newrelic(&headers, "1", TRANSPORT_HTTP).unwrap();
+        assert_eq!(payload.trace_id, "d6b4ba0c3a712ca1");
+        assert_eq!(payload.parent_span_id.as_deref(), Some("27ddd2d8890283b4"));
+        assert_eq!(payload.caller(), "App/33/5043/HTTP");
+        let nr = payload.nr.unwrap();
+        assert_eq!(nr.transaction_id.as_deref(), Some("5569065a5b1313bd"));
+        assert_eq!(nr.sampled, Some(true));
+        assert_eq!(nr.priority, Some(Priority(1.5)));
+
+        assert!(accept_newrelic(&headers, "33", TRANSPORT_HTTP).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::time::SystemTime;
+
+use crate::app_run::AppRun;
+use crate::config::ErrorCollectorConfig;
+use crate::domain_defs::AgentRunId;
+use crate::payloads::errors::{CollectorPayload, StackFrame, TracedError};
+use crate::stack_trace::{panic_stack_trace, stack_trace};
+
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorData {
+    pub(crate) when: SystemTime,
+    pub(crate) class: String,
+    pub(crate) message: String,
+    pub(crate) stack_trace: Option<Vec<StackFrame>>,
+    /// Expected errors don't affect the error rate and Apdex.
+    pub(crate) expected: bool,
+}
+
+impl ErrorData {
+    pub(crate) fn new(class: &str, message: &str, backtrace: &Backtrace) -> Self {
+        Self {
+            when: SystemTime::now(),
+            class: class.to_owned(),
+            message: message.to_owned(),
+            stack_trace: stack_trace(backtrace),
+            expected: false,
+        }
+    }
+
+    pub(crate) fn from_error<E: std::error::Error + ?Sized>(
+        error: &E,
+        backtrace: &Backtrace,
+    ) -> Self {
+        let type_name = std::any::type_name::<E>();
+        let class = if type_name.starts_with("dyn ") {
+            debug_class_name(error).unwrap_or_else(|| type_name.to_owned())
+        } else {
+            type_name.to_owned()
+        };
+        Self::new(&class, &chain_message(error), backtrace)
+    }
+
+    /// Records a panic with the class `"panic"`.
+    ///
+    /// If the backtrace is not captured, the location of the panic is used as the stack trace.
+    pub(crate) fn from_panic(info: &PanicHookInfo<'_>, backtrace: &Backtrace) -> Self {
+        let payload = info.payload();
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            (*message).to_owned()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "Box<dyn Any>".to_owned()
+        };
+        let stack_trace = panic_stack_trace(backtrace).or_else(|| {
+            let location = info.location()?;
+            Some(vec![StackFrame {
+                name: None,
+                filepath: Some(location.file().to_owned()),
+                line: Some(location.line()),
+            }])
+        });
+        Self {
+            when: SystemTime::now(),
+            class: "panic".to_owned(),
+            message,
+            stack_trace,
+            expected: false,
+        }
+    }
+
+    /// Applies the error collector config. Returns `None` if the error is ignored.
+    ///
+    /// The server-side config in `run` takes precedence over the local one.
+    pub(crate) fn filter(
+        mut self,
+        config: &ErrorCollectorConfig,
+        run: Option<&AppRun>,
+    ) -> Option<Self> {
+        let enabled = run
+            .and_then(|run| run.error_collector_enabled)
+            .unwrap_or(config.enabled);
+        if !enabled {
+            return None;
+        }
+        // Errors recorded for HTTP responses have the status code as the class.
+        let status_code = self.class.parse::<u16>().ok();
+        if let Some(status_code) = status_code {
+            let ignore_status_codes = run
+                .and_then(|run| run.ignore_status_codes.as_deref())
+                .unwrap_or(&config.ignore_status_codes);
+            if ignore_status_codes.contains(&status_code) {
+                return None;
+            }
+        }
+        if config.ignore_classes.contains(&self.class) {
+            return None;
+        }
+        if let Some(messages) = config.ignore_messages.get(&self.class) {
+            if messages
+                .iter()
+                .any(|message| self.message.contains(message.as_str()))
+            {
+                return None;
+            }
+        }
+        self.expected = config.expected_classes.contains(&self.class)
+            || status_code
+                .is_some_and(|status_code| config.expected_status_codes.contains(&status_code));
+        Some(self)
+    }
+}
+
+/// Joins the messages of the error and its sources with `": "`, from the outermost one.
+fn chain_message<E: std::error::Error + ?Sized>(error: &E) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        // Errors often include the message of the source in their own message.
+        if !message.ends_with(&cause_message) {
+            message.push_str(": ");
+            message.push_str(&cause_message);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// Extracts the leading identifier (the struct or variant name) of the `Debug` representation.
+fn debug_class_name<E: std::fmt::Debug + ?Sized>(error: &E) -> Option<String> {
+    let debug = format!("{:?}", error);
+    let len = debug
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(debug.len());
+    let name = &debug[..len];
+    if name.is_empty() || name.starts_with(|c: char| c.is_numeric()) {
+        None
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HarvestErrors {
+    errors: Vec<TracedError>,
+}
+
+impl HarvestErrors {
+    pub(crate) fn new() -> Self {
+        Self { errors: vec![] }
+    }
+
+    pub(crate) fn push(&mut self, error: TracedError) {
+        if self.errors.len() < crate::limits::MAX_HARVEST_ERRORS {
+            self.errors.push(error);
+        }
+    }
+
+    pub(crate) fn into_payload(self, agent_run_id: &AgentRunId) -> CollectorPayload {
+        CollectorPayload {
+            agent_run_id: agent_run_id.clone(),
+            errors: self.errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_class() {
+        let backtrace = Backtrace::disabled();
+        let error = "x".parse::<i32>().unwrap_err();
+        assert_eq!(
+            ErrorData::from_error(&error, &backtrace).class,
+            "core::num::error::ParseIntError"
+        );
+        let error: &dyn std::error::Error = &error;
+        let data = ErrorData::from_error(error, &backtrace);
+        assert_eq!(data.class, "ParseIntError");
+        assert_eq!(data.message, "invalid digit found in string");
+        assert!(data.stack_trace.is_none());
+    }
+
+    #[test]
+    fn test_filter() {
+        let backtrace = Backtrace::disabled();
+        let mut config = ErrorCollectorConfig::default();
+        config.ignore_classes.push("IgnoredError".to_owned());
+        config
+            .ignore_messages
+            .insert("TimeoutError".to_owned(), vec!["health".to_owned()]);
+        config.expected_classes.push("ValidationError".to_owned());
+        config.expected_status_codes.push(429);
+
+        let filter = |config: &ErrorCollectorConfig, class: &str, message: &str| {
+            ErrorData::new(class, message, &backtrace)
+                .filter(config, None)
+                .map(|error| error.expected)
+        };
+        assert_eq!(filter(&config, "404", "Not Found"), None);
+        assert_eq!(filter(&config, "500", "Internal Server Error"), Some(false));
+        assert_eq!(filter(&config, "429", "Too Many Requests"), Some(true));
+        assert_eq!(filter(&config, "IgnoredError", "ignored"), None);
+        assert_eq!(
+            filter(&config, "TimeoutError", "GET /health timed out"),
+            None
+        );
+        assert_eq!(
+            filter(&config, "TimeoutError", "GET /users timed out"),
+            Some(false)
+        );
+        assert_eq!(
+            filter(&config, "ValidationError", "invalid name"),
+            Some(true)
+        );
+
+        config.enabled = false;
+        assert_eq!(filter(&config, "500", "Internal Server Error"), None);
+    }
+
+    #[test]
+    fn test_chain_message() {
+        #[derive(Debug, thiserror::Error)]
+        enum ConfigError {
+            #[error("failed to read config")]
+            Read(#[source] std::io::Error),
+            #[error("invalid port: {0}")]
+            Port(#[from] std::num::ParseIntError),
+        }
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert_eq!(
+            chain_message(&ConfigError::Read(io_error)),
+            "failed to read config: no such file"
+        );
+        let parse_error = "x".parse::<u16>().unwrap_err();
+        assert_eq!(
+            chain_message(&ConfigError::Port(parse_error)),
+            "invalid port: invalid digit found in string"
+        );
+    }
+}
@@ -3,10 +3,13 @@
 
 use std::time::{Duration, Instant};
 
+use crate::analytics_events::AnalyticsEvents;
 use crate::app_run::AppRun;
 use crate::collector::{collector_request, RpmError};
+use crate::errors::HarvestErrors;
 use crate::metrics::MetricTable;
-use crate::payloads::analytics_events::AnalyticsEventWithAttrs;
+use crate::payloads::analytics_events::{AnalyticsEventWithAttrs, CustomEvent};
+use crate::payloads::span_events::SpanEvent;
 use crate::transaction_trace::HarvestTraces;
 
 #[derive(Debug)]
@@ -16,9 +19,13 @@ pub(crate) struct Harvest {
     custom_events_timer: HarvestTimer,
     txn_events_timer: HarvestTimer,
     error_events_timer: HarvestTimer,
-    pub(crate) txn_events: Vec<AnalyticsEventWithAttrs>,
+    pub(crate) txn_events: AnalyticsEvents<AnalyticsEventWithAttrs>,
+    pub(crate) error_events: AnalyticsEvents<AnalyticsEventWithAttrs>,
+    pub(crate) custom_events: AnalyticsEvents<CustomEvent>,
+    pub(crate) span_events: AnalyticsEvents<SpanEvent>,
     pub(crate) metric_table: MetricTable,
     pub(crate) txn_traces: HarvestTraces,
+    pub(crate) error_traces: HarvestErrors,
 }
 
 impl Harvest {
@@ -34,9 +41,13 @@ impl Harvest {
             custom_events_timer: new_timer(run.custom_events_period),
             txn_events_timer: new_timer(run.txn_events_period),
             error_events_timer: new_timer(run.error_events_period),
-            txn_events: vec![],
+            txn_events: AnalyticsEvents::new(run.max_txn_events),
+            error_events: AnalyticsEvents::new(run.max_error_events),
+            custom_events: AnalyticsEvents::new(run.max_custom_events),
+            span_events: AnalyticsEvents::new(run.max_span_events),
             metric_table: MetricTable::new(),
             txn_traces: HarvestTraces::new(),
+            error_traces: HarvestErrors::new(),
         }
     }
 
@@ -56,19 +67,30 @@ impl Harvest {
                 &mut self.txn_traces,
                 HarvestTraces::new(),
             ));
+            ready.error_traces = Some(std::mem::replace(
+                &mut self.error_traces,
+                HarvestErrors::new(),
+            ));
         }
         if self.span_events_timer.ready(now, force) {
             log::debug!("Processing span events...");
+            let renewed = self.span_events.renew();
+            ready.span_events = Some(std::mem::replace(&mut self.span_events, renewed));
         }
         if self.custom_events_timer.ready(now, force) {
             log::debug!("Processing custom events...");
+            let renewed = self.custom_events.renew();
+            ready.custom_events = Some(std::mem::replace(&mut self.custom_events, renewed));
         }
         if self.txn_events_timer.ready(now, force) {
             log::debug!("Processing txn events...");
-            ready.txn_events = Some(std::mem::replace(&mut self.txn_events, vec![]));
+            let renewed = self.txn_events.renew();
+            ready.txn_events = Some(std::mem::replace(&mut self.txn_events, renewed));
         }
         if self.error_events_timer.ready(now, force) {
             log::debug!("Processing error events...");
+            let renewed = self.error_events.renew();
+            ready.error_events = Some(std::mem::replace(&mut self.error_events, renewed));
         }
         ready
     }
@@ -93,16 +115,22 @@ impl HarvestTimer {
 
 #[derive(Debug, Default)]
 pub(crate) struct HarvestReady {
-    pub(crate) txn_events: Option<Vec<AnalyticsEventWithAttrs>>,
+    pub(crate) txn_events: Option<AnalyticsEvents<AnalyticsEventWithAttrs>>,
+    pub(crate) error_events: Option<AnalyticsEvents<AnalyticsEventWithAttrs>>,
+    pub(crate) custom_events: Option<AnalyticsEvents<CustomEvent>>,
+    pub(crate) span_events: Option<AnalyticsEvents<SpanEvent>>,
     pub(crate) metric_table: Option<MetricTable>,
     pub(crate) txn_traces: Option<HarvestTraces>,
+    pub(crate) error_traces: Option<HarvestErrors>,
 }
 
 impl HarvestReady {
     pub(crate) fn harvest(self, run: &AppRun) -> Result<(), RpmError> {
         if let Some(metric_table) = self.metric_table {
             log::debug!("Sending metrics traces...");
-            let payload = metric_table.payload(&run.agent_run_id);
+            let payload = metric_table
+                .apply_rules(&run.metric_rules)
+                .payload(&run.agent_run_id);
             // TODO: ignore specific errors & save harvest data when appropriate
             collector_request(run, "metric_data", &payload)?;
         }
@@ -114,25 +142,56 @@ impl HarvestReady {
                 collector_request(run, "transaction_sample_data", &payload)?;
             }
         }
+        if let Some(error_traces) = self.error_traces {
+            log::debug!("Sending error traces...");
+            let payload = error_traces.into_payload(&run.agent_run_id);
+            if !payload.is_empty() {
+                // TODO: ignore specific errors & save harvest data when appropriate
+                collector_request(run, "error_data", &payload)?;
+            }
+        }
         if let Some(txn_events) = self.txn_events {
-            use crate::payloads::analytics_events::{CollectorPayload, Properties};
-
             log::debug!("Sending txn events...");
             // TODO: ignore specific errors & save harvest data when appropriate
-            collector_request(
-                run,
-                "analytic_event_data",
-                &CollectorPayload {
-                    agent_run_id: run.agent_run_id.clone(),
-                    properties: Properties {
-                        reservoir_size: txn_events.capacity() as i32,
-                        events_seen: txn_events.len() as i32,
-                    },
-                    events: txn_events,
-                },
-            )?;
+            collector_request(run, "analytic_event_data", &events_payload(run, txn_events))?;
+        }
+        if let Some(error_events) = self.error_events {
+            if !error_events.is_empty() {
+                log::debug!("Sending error events...");
+                // TODO: ignore specific errors & save harvest data when appropriate
+                collector_request(run, "error_event_data", &events_payload(run, error_events))?;
+            }
+        }
+        if let Some(custom_events) = self.custom_events {
+            if !custom_events.is_empty() {
+                log::debug!("Sending custom events...");
+                // TODO: ignore specific errors & save harvest data when appropriate
+                collector_request(
+                    run,
+                    "custom_event_data",
+                    &events_payload(run, custom_events),
+                )?;
+            }
+        }
+        if let Some(span_events) = self.span_events {
+            if !span_events.is_empty() {
+                log::debug!("Sending span events...");
+                // TODO: ignore specific errors & save harvest data when appropriate
+                collector_request(run, "span_event_data", &events_payload(run, span_events))?;
+            }
         }
 
         Ok(())
     }
 }
+
+fn events_payload<E>(
+    run: &AppRun,
+    events: AnalyticsEvents<E>,
+) -> crate::payloads::analytics_events::CollectorPayload<E> {
+    crate::payloads::analytics_events::CollectorPayload {
+        agent_run_id: run.agent_run_id.clone(),
+        properties: events.properties(),
+        events: events.into_events(),
+    }
+}
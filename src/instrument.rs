@@ -0,0 +1,57 @@
+// Copyright 2020 Masaki Hara.
+
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::Transaction;
+
+/// Extension trait to run futures in a transaction.
+pub trait InstrumentNewRelic: Sized {
+    /// Makes the transaction current while the future is polled.
+    ///
+    /// Segments started in the future can find the transaction with [`Transaction::current`].
+    /// Futures running concurrently in the same transaction should use separate handles given
+    /// by [`Transaction::new_thread`], so that their segments are not nested in each other.
+    fn instrument_newrelic(self, txn: &Transaction) -> Instrumented<Self> {
+        Instrumented {
+            inner: self,
+            txn: txn.clone(),
+        }
+    }
+}
+
+impl<F: Future> InstrumentNewRelic for F {}
+
+pin_project! {
+    /// A future running in a transaction, created by
+    /// [`instrument_newrelic`](InstrumentNewRelic::instrument_newrelic).
+    #[derive(Debug)]
+    #[must_use = "futures do nothing unless polled"]
+    pub struct Instrumented<F> {
+        #[pin]
+        inner: F,
+        txn: Transaction,
+    }
+}
+
+impl<F> Instrumented<F> {
+    pub fn transaction(&self) -> &Transaction {
+        &self.txn
+    }
+
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = this.txn.enter();
+        this.inner.poll(cx)
+    }
+}
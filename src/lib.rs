@@ -4,29 +4,61 @@
 #![deny(unsafe_code)]
 
 use parking_lot::Mutex;
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::app_run::AppRun;
+use crate::attributes::{custom_event_attributes, AttributeFilter};
+pub use crate::attributes::{AttributeError, AttributeValue};
 use crate::collector::RpmError;
 pub use crate::config::Config;
+use crate::errors::ErrorData;
 use crate::harvest::Harvest;
+#[cfg(feature = "futures")]
+pub use crate::instrument::{InstrumentNewRelic, Instrumented};
+use crate::metrics::Metric;
+pub use crate::metrics::{MetricError, MetricSummary};
+use crate::payloads::analytics_events::{
+    AnalyticsEvent, AnalyticsEventWithAttrs, CustomEvent, CustomEventIntrinsics, ErrorEvent,
+};
+use crate::payloads::{AgentAttrs, UserAttrs};
+use crate::priority::Priority;
+pub use crate::segments::{
+    DatastoreSegment, ExternalSegment, MessageDestinationType, MessageProducerSegment, SegmentGuard,
+};
 use crate::sync_util::Shutdown;
 pub use crate::transaction::{Transaction, TransactionGuard, WebRequest};
 
+mod adaptive_sampler;
+mod analytics_events;
 mod apdex;
 mod app_run;
+mod attributes;
 mod collector;
 pub mod config;
 mod connect_reply;
+mod cross_process;
+mod distributed_tracing;
 mod domain_defs;
+mod errors;
 mod harvest;
+#[cfg(feature = "futures")]
+mod instrument;
 mod limits;
 mod metric_names;
 mod metrics;
 mod payloads;
+mod priority;
+mod queuing;
+mod rules;
+mod segments;
+mod stack_trace;
 mod sync_util;
+mod tracing;
 mod transaction;
 mod transaction_trace;
 mod utilization;
@@ -96,7 +128,7 @@ impl Application {
     }
 
     pub fn start_transaction(&self, name: &str) -> TransactionGuard {
-        Transaction::new(&self.inner, name, None)
+        Transaction::new(&self.inner, name, None, false)
     }
 
     pub fn start_web_transaction<T: Into<WebRequest>>(
@@ -104,7 +136,117 @@ impl Application {
         name: &str,
         request: T,
     ) -> TransactionGuard {
-        Transaction::new(&self.inner, name, Some(request.into()))
+        Transaction::new(&self.inner, name, Some(request.into()), false)
+    }
+
+    /// Starts a transaction processing a message received from a message broker.
+    ///
+    /// The transaction is named `OtherTransaction/Message/<library>/<type>/Named/<name>`.
+    pub fn start_message_transaction(
+        &self,
+        library: &str,
+        destination_type: MessageDestinationType,
+        destination_name: &str,
+    ) -> TransactionGuard {
+        let name = crate::metric_names::message_destination_name(
+            library,
+            destination_type,
+            destination_name,
+            false,
+        );
+        Transaction::new(&self.inner, &name, None, true)
+    }
+
+    /// Records an event of a user-defined type, queryable in NRQL.
+    ///
+    /// The event type must consist of alphanumerics, colons, underscores and spaces.
+    /// At most 64 attributes are allowed.
+    pub fn record_custom_event<I, K, V>(
+        &self,
+        event_type: &str,
+        attributes: I,
+    ) -> Result<(), AttributeError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<AttributeValue>,
+    {
+        crate::attributes::validate_event_type(event_type)?;
+        let user_attrs = custom_event_attributes(attributes)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let event = CustomEvent {
+            intrinsics: CustomEventIntrinsics {
+                event_type: event_type.to_owned(),
+                timestamp: timestamp.as_secs_f64(),
+            },
+            user_attrs,
+        };
+        let mut state = self.inner.state.lock();
+        if let AppState::Running { run, harvest } = &mut *state {
+            if run.collect_custom_events {
+                harvest.custom_events.push(event, Priority::new());
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a value of the custom metric `Custom/<name>`.
+    pub fn record_custom_metric(&self, name: &str, value: f64) -> Result<(), MetricError> {
+        self.record_custom_metric_internal(name, Metric::from_value(value)?);
+        Ok(())
+    }
+
+    /// Increments the count of the custom metric `Custom/<name>`.
+    pub fn record_custom_metric_count(&self, name: &str, count: u64) {
+        self.record_custom_metric_internal(name, Metric::from_count(count as f64));
+    }
+
+    /// Records pre-aggregated values of the custom metric `Custom/<name>`.
+    pub fn record_custom_metric_summary(
+        &self,
+        name: &str,
+        summary: &MetricSummary,
+    ) -> Result<(), MetricError> {
+        self.record_custom_metric_internal(name, Metric::from_summary(summary)?);
+        Ok(())
+    }
+
+    fn record_custom_metric_internal(&self, name: &str, metric: Metric) {
+        let mut state = self.inner.state.lock();
+        if let AppState::Running { harvest, .. } = &mut *state {
+            let name = crate::metric_names::custom_segment_metric(name);
+            harvest.metric_table.add(&name, None, metric, false);
+        }
+    }
+
+    /// Installs a panic hook recording panics as errors, chained onto the existing hook.
+    ///
+    /// A panic is recorded in the innermost transaction of this application started in the
+    /// panicking thread. Otherwise it is recorded as an error event outside of transactions.
+    /// The handles from [`Transaction::new_thread`] are not known as the current transaction of
+    /// the thread they are moved to, so panics there are recorded outside of transactions.
+    ///
+    /// The hook never blocks: a panic is not recorded if the panicking thread holds the state of
+    /// the application or the transaction. The collected data is sent by the harvest thread
+    /// shortly after, without waiting for the next harvest.
+    ///
+    /// The backtrace is captured if enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+    ///
+    /// Calling it more than once for the same application does nothing.
+    pub fn install_panic_hook(&self) {
+        if self.inner.panic_hook_installed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let app = Arc::downgrade(&self.inner);
+        let prev_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            prev_hook(info);
+            if let Some(app) = app.upgrade() {
+                app.notice_panic(info);
+            }
+        }));
     }
 
     pub fn shutdown(&self) {
@@ -115,8 +257,10 @@ impl Application {
 #[derive(Debug)]
 struct ApplicationInner {
     config: Config,
+    attribute_filter: AttributeFilter,
     state: Mutex<AppState>,
     shutdown: Shutdown,
+    panic_hook_installed: AtomicBool,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -136,8 +280,10 @@ impl ApplicationInner {
         };
         ApplicationInner {
             config: config.clone(),
+            attribute_filter: AttributeFilter::new(config),
             state: Mutex::new(state),
             shutdown: Shutdown::new(),
+            panic_hook_installed: AtomicBool::new(false),
         }
     }
 
@@ -189,11 +335,12 @@ impl ApplicationInner {
         }
         loop {
             self.shutdown.sleep(Duration::from_secs(1))?;
+            let force = self.shutdown.take_harvest_request();
             // Only invoke Harvest::ready() during locking.
             let ready = {
                 let mut state = self.state.lock();
                 if let AppState::Running { run, harvest } = &mut *state {
-                    Some((Arc::clone(run), harvest.ready(run, false)))
+                    Some((Arc::clone(run), harvest.ready(run, force)))
                 } else {
                     None
                 }
@@ -212,6 +359,94 @@ impl ApplicationInner {
         }
     }
 
+    /// Records a panic without blocking, as it is called in the panic hook.
+    fn notice_panic(self: &Arc<Self>, info: &PanicHookInfo<'_>) {
+        let error = ErrorData::from_panic(info, &Backtrace::capture());
+        if let Some(txn) = Transaction::current_in(self) {
+            let run = self.try_current_run();
+            if let Some(error) = error.filter(&self.config.error_collector, run.as_deref()) {
+                txn.try_notice_error(error);
+            }
+        } else {
+            self.try_notice_error_outside_txn(error);
+        }
+        // The transaction is ended by unwinding before the harvest thread wakes up.
+        self.shutdown.request_harvest();
+    }
+
+    /// Records an error event outside of transactions, unless the state is locked.
+    fn try_notice_error_outside_txn(&self, error: ErrorData) {
+        let mut state = if let Some(state) = self.state.try_lock() {
+            state
+        } else {
+            return;
+        };
+        if let AppState::Running { run, harvest } = &mut *state {
+            let error = if let Some(error) = error.filter(&self.config.error_collector, Some(run)) {
+                error
+            } else {
+                return;
+            };
+            if error.expected {
+                harvest.metric_table.add_count(
+                    crate::metric_names::ERRORS_EXPECTED_ALL,
+                    None,
+                    1.0,
+                    true,
+                );
+            } else {
+                harvest
+                    .metric_table
+                    .add_count(crate::metric_names::ERRORS_ALL, None, 1.0, true);
+                harvest.metric_table.add_count(
+                    crate::metric_names::errors_rollup_name(false),
+                    None,
+                    1.0,
+                    true,
+                );
+            }
+            if run.collect_error_events && self.config.error_collector.capture_events {
+                let timestamp = error.when.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let event = AnalyticsEventWithAttrs {
+                    event: AnalyticsEvent::TransactionError(ErrorEvent {
+                        class: error.class.clone(),
+                        message: error.message.clone(),
+                        expected: error.expected,
+                        timestamp: timestamp.as_secs_f64(),
+                        transaction_name: "Unknown".to_owned(),
+                        shared: None,
+                    }),
+                    user_attrs: UserAttrs::default(),
+                    agent_attrs: AgentAttrs::default(),
+                };
+                harvest.error_events.push(event, Priority::new());
+            }
+        }
+    }
+
+    /// Applies the local and the server-side error collector configs.
+    fn filter_error(&self, error: ErrorData) -> Option<ErrorData> {
+        error.filter(&self.config.error_collector, self.current_run().as_deref())
+    }
+
+    /// Returns the current run if connected.
+    fn current_run(&self) -> Option<Arc<AppRun>> {
+        if let AppState::Running { run, .. } = &*self.state.lock() {
+            Some(Arc::clone(run))
+        } else {
+            None
+        }
+    }
+
+    /// Same as `current_run`, but returns `None` if the state is locked.
+    fn try_current_run(&self) -> Option<Arc<AppRun>> {
+        if let AppState::Running { run, .. } = &*self.state.try_lock()? {
+            Some(Arc::clone(run))
+        } else {
+            None
+        }
+    }
+
     fn shutdown(self: &Arc<Self>) {
         log::debug!("shutting down...");
         let mut old_state = {
@@ -1,11 +1,14 @@
 // Copyright 2020 New Relic Corporation. (for the original go-agent)
 // Copyright 2020 Masaki Hara.
 
-// const APDEX_ROLLUP: &str = "Apdex";
-// const APDEX_PREFIX: &str = "Apdex/";
+use crate::segments::{DatastoreMetricKey, ExternalMetricKey, MessageDestinationType};
+
+pub(crate) const APDEX_ROLLUP: &str = "Apdex";
+const APDEX_PREFIX: &str = "Apdex/";
 
 pub(crate) const WEB_METRIC_PREFIX: &str = "WebTransaction/Go";
 pub(crate) const BACKGROUND_METRIC_PREFIX: &str = "OtherTransaction/Go";
+pub(crate) const MESSAGE_METRIC_PREFIX: &str = "OtherTransaction/Message";
 
 const WEB_ROLLUP: &str = "WebTransaction";
 const BACKGROUND_ROLLUP: &str = "OtherTransaction/all";
@@ -21,12 +24,20 @@ pub(crate) fn rollup_name(is_web: bool) -> &'static str {
     }
 }
 
-pub(crate) fn total_time_name(name: &str, is_web: bool) -> String {
-    let name_without_first_segment = if let Some(pos) = name.find('/') {
+fn remove_first_segment(name: &str) -> &str {
+    if let Some(pos) = name.find('/') {
         &name[pos + 1..]
     } else {
-        &name
-    };
+        name
+    }
+}
+
+pub(crate) fn apdex_name(name: &str) -> String {
+    format!("{}{}", APDEX_PREFIX, remove_first_segment(name))
+}
+
+pub(crate) fn total_time_name(name: &str, is_web: bool) -> String {
+    let name_without_first_segment = remove_first_segment(name);
     let prefix = if is_web {
         TOTAL_TIME_WEB
     } else {
@@ -43,7 +54,197 @@ pub(crate) fn total_time_rollup_name(is_web: bool) -> &'static str {
     }
 }
 
-// const ERRORS_PREFIX: &str = "Errors/";
+const CUSTOM_SEGMENT_PREFIX: &str = "Custom/";
+
+pub(crate) fn custom_segment_metric(name: &str) -> String {
+    format!("{}{}", CUSTOM_SEGMENT_PREFIX, name)
+}
+
+pub(crate) const DATASTORE_ALL: &str = "Datastore/all";
+pub(crate) const DATASTORE_WEB: &str = "Datastore/allWeb";
+pub(crate) const DATASTORE_OTHER: &str = "Datastore/allOther";
+
+pub(crate) struct RollupNames {
+    pub(crate) all: String,
+    pub(crate) web: String,
+    pub(crate) other: String,
+}
+
+pub(crate) fn datastore_product_metric(key: &DatastoreMetricKey) -> RollupNames {
+    RollupNames {
+        all: format!("Datastore/{}/all", key.product),
+        web: format!("Datastore/{}/allWeb", key.product),
+        other: format!("Datastore/{}/allOther", key.product),
+    }
+}
+
+pub(crate) fn datastore_operation_metric(key: &DatastoreMetricKey) -> String {
+    format!("Datastore/operation/{}/{}", key.product, key.operation)
+}
+
+pub(crate) fn datastore_statement_metric(key: &DatastoreMetricKey) -> String {
+    format!(
+        "Datastore/statement/{}/{}/{}",
+        key.product, key.collection, key.operation
+    )
+}
+
+pub(crate) fn datastore_instance_metric(key: &DatastoreMetricKey) -> String {
+    format!(
+        "Datastore/instance/{}/{}/{}",
+        key.product, key.host, key.port_path_or_id
+    )
+}
+
+pub(crate) fn datastore_scoped_metric(key: &DatastoreMetricKey) -> String {
+    if key.collection.is_empty() {
+        datastore_operation_metric(key)
+    } else {
+        datastore_statement_metric(key)
+    }
+}
+
+pub(crate) const EXTERNAL_ALL: &str = "External/all";
+pub(crate) const EXTERNAL_WEB: &str = "External/allWeb";
+pub(crate) const EXTERNAL_OTHER: &str = "External/allOther";
+
+pub(crate) fn external_host_metric(key: &ExternalMetricKey) -> String {
+    format!("External/{}/all", key.host)
+}
+
+pub(crate) fn external_scoped_metric(key: &ExternalMetricKey) -> String {
+    if is_cross_process(key) {
+        external_transaction_metric(key)
+    } else {
+        format!("External/{}/{}/{}", key.host, key.library, key.method)
+    }
+}
+
+pub(crate) fn is_cross_process(key: &ExternalMetricKey) -> bool {
+    !key.cross_process_id.is_empty() && !key.transaction_name.is_empty()
+}
+
+pub(crate) fn external_app_metric(key: &ExternalMetricKey) -> String {
+    format!("ExternalApp/{}/{}/all", key.host, key.cross_process_id)
+}
+
+pub(crate) fn external_transaction_metric(key: &ExternalMetricKey) -> String {
+    format!(
+        "ExternalTransaction/{}/{}/{}",
+        key.host, key.cross_process_id, key.transaction_name
+    )
+}
+
+pub(crate) fn client_application_metric(client_id: &str) -> String {
+    format!("ClientApplication/{}/all", client_id)
+}
+
+fn message_destination(
+    library: &str,
+    destination_type: MessageDestinationType,
+    action: Option<&str>,
+    destination_name: &str,
+    destination_temporary: bool,
+) -> String {
+    let library = if library.is_empty() {
+        "Unknown"
+    } else {
+        library
+    };
+    let action = action.map(|a| format!("{}/", a)).unwrap_or_default();
+    let destination = if destination_temporary {
+        "Temp".to_owned()
+    } else if destination_name.is_empty() {
+        "Named/Unknown".to_owned()
+    } else {
+        format!("Named/{}", destination_name)
+    };
+    format!(
+        "{}/{}/{}{}",
+        library,
+        destination_type.as_str(),
+        action,
+        destination
+    )
+}
+
+/// Names a consumer transaction as `<library>/<type>/Named/<name>` (or `.../Temp`).
+pub(crate) fn message_destination_name(
+    library: &str,
+    destination_type: MessageDestinationType,
+    destination_name: &str,
+    destination_temporary: bool,
+) -> String {
+    message_destination(
+        library,
+        destination_type,
+        None,
+        destination_name,
+        destination_temporary,
+    )
+}
+
+pub(crate) fn message_producer_metric(
+    library: &str,
+    destination_type: MessageDestinationType,
+    destination_name: &str,
+    destination_temporary: bool,
+) -> String {
+    format!(
+        "MessageBroker/{}",
+        message_destination(
+            library,
+            destination_type,
+            Some("Produce"),
+            destination_name,
+            destination_temporary,
+        )
+    )
+}
+
+pub(crate) const QUEUE_METRIC: &str = "WebFrontend/QueueTime";
+
+pub(crate) const ERRORS_ALL: &str = "Errors/all";
+const ERRORS_WEB: &str = "Errors/allWeb";
+const ERRORS_OTHER: &str = "Errors/allOther";
+const ERRORS_PREFIX: &str = "Errors/";
+pub(crate) const ERRORS_EXPECTED_ALL: &str = "ErrorsExpected/all";
+
+pub(crate) fn errors_rollup_name(is_web: bool) -> &'static str {
+    if is_web {
+        ERRORS_WEB
+    } else {
+        ERRORS_OTHER
+    }
+}
+
+pub(crate) fn errors_name(name: &str) -> String {
+    format!("{}{}", ERRORS_PREFIX, name)
+}
+
+const DURATION_BY_CALLER_PREFIX: &str = "DurationByCaller/";
+const TRANSPORT_DURATION_PREFIX: &str = "TransportDuration/";
+const ERRORS_BY_CALLER_PREFIX: &str = "ErrorsByCaller/";
+
+fn caller_metric(prefix: &str, caller: &str) -> RollupNames {
+    RollupNames {
+        all: format!("{}{}/all", prefix, caller),
+        web: format!("{}{}/allWeb", prefix, caller),
+        other: format!("{}{}/allOther", prefix, caller),
+    }
+}
+
+pub(crate) fn duration_by_caller_metric(caller: &str) -> RollupNames {
+    caller_metric(DURATION_BY_CALLER_PREFIX, caller)
+}
+
+pub(crate) fn transport_duration_metric(caller: &str) -> RollupNames {
+    caller_metric(TRANSPORT_DURATION_PREFIX, caller)
+}
+
+pub(crate) fn errors_by_caller_metric(caller: &str) -> RollupNames {
+    caller_metric(ERRORS_BY_CALLER_PREFIX, caller)
+}
 
 // "HttpDispatcher" metric is used for the overview graph, and
 // therefore should only be made for web transactions.
@@ -57,6 +258,63 @@ pub(crate) const SUPPORTABILITY_DROPPED: &str = "Supportability/MetricsDropped";
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_datastore_metrics() {
+        let key = DatastoreMetricKey {
+            product: "Postgres".to_owned(),
+            collection: "users".to_owned(),
+            operation: "SELECT".to_owned(),
+            host: "db".to_owned(),
+            port_path_or_id: "5432".to_owned(),
+        };
+        assert_eq!(
+            datastore_scoped_metric(&key),
+            "Datastore/statement/Postgres/users/SELECT"
+        );
+        assert_eq!(
+            datastore_instance_metric(&key),
+            "Datastore/instance/Postgres/db/5432"
+        );
+        let key = DatastoreMetricKey {
+            collection: "".to_owned(),
+            ..key
+        };
+        assert_eq!(
+            datastore_scoped_metric(&key),
+            "Datastore/operation/Postgres/SELECT"
+        );
+    }
+
+    #[test]
+    fn test_message_metrics() {
+        assert_eq!(
+            message_producer_metric(
+                "RabbitMQ",
+                MessageDestinationType::Exchange,
+                "events",
+                false
+            ),
+            "MessageBroker/RabbitMQ/Exchange/Produce/Named/events"
+        );
+        assert_eq!(
+            message_producer_metric("RabbitMQ", MessageDestinationType::Queue, "", false),
+            "MessageBroker/RabbitMQ/Queue/Produce/Named/Unknown"
+        );
+        assert_eq!(
+            message_producer_metric("", MessageDestinationType::Topic, "reply-1", true),
+            "MessageBroker/Unknown/Topic/Produce/Temp"
+        );
+        assert_eq!(
+            message_destination_name("Kafka", MessageDestinationType::Topic, "orders", false),
+            "Kafka/Topic/Named/orders"
+        );
+    }
+
+    #[test]
+    fn test_apdex_name() {
+        assert_eq!(apdex_name("WebTransaction/Go/test"), "Apdex/Go/test");
+    }
+
     #[test]
     fn test_total_time_name() {
         assert_eq!(
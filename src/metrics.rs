@@ -3,10 +3,13 @@
 
 use std::collections::HashMap;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
 
+use crate::apdex::ApdexZone;
 use crate::domain_defs::AgentRunId;
 use crate::limits::MAX_METRICS;
 use crate::payloads::metrics::{CollectorPayload, MetricId, MetricValue};
+use crate::rules::MetricRules;
 
 impl From<Metric> for MetricValue {
     fn from(m: Metric) -> Self {
@@ -14,17 +17,49 @@ impl From<Metric> for MetricValue {
             count_satisfied: m.count_satisfied,
             total_tolerated: m.total_tolerated,
             exclusive_failed: m.exclusive_failed,
-            min: m.min,
-            max: m.max,
+            // A metric built only from counts has no min/max.
+            min: if m.min.is_finite() { m.min } else { 0.0 },
+            max: if m.max.is_finite() { m.max } else { 0.0 },
             sum_squares: m.sum_squares,
         }
     }
 }
 
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum MetricError {
+    #[error("metric value is not a finite number")]
+    NonFinite,
+}
+
+/// Pre-aggregated observations of a custom metric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricSummary {
+    pub count: u64,
+    pub total: f64,
+    pub min: f64,
+    pub max: f64,
+    pub sum_squares: f64,
+    #[doc(hidden)]
+    pub __non_exhaustive: (),
+}
+
+impl MetricSummary {
+    pub fn new(count: u64, total: f64, min: f64, max: f64, sum_squares: f64) -> Self {
+        Self {
+            count,
+            total,
+            min,
+            max,
+            sum_squares,
+            __non_exhaustive: (),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct MetricTable {
     start: Instant,
-    failed_harvests: usize,
     max_table_size: usize,
     metrics: HashMap<MetricId, Metric>,
 }
@@ -33,7 +68,6 @@ impl MetricTable {
     pub(crate) fn new() -> Self {
         Self {
             start: Instant::now(),
-            failed_harvests: 0,
             max_table_size: MAX_METRICS,
             metrics: HashMap::new(),
         }
@@ -56,7 +90,19 @@ impl MetricTable {
         self.add(name, scope, metric, forced);
     }
 
-    fn add(&mut self, name: &str, scope: Option<&str>, metric: Metric, forced: bool) {
+    pub(crate) fn add_apdex(
+        &mut self,
+        name: &str,
+        scope: Option<&str>,
+        threshold: Duration,
+        zone: ApdexZone,
+        forced: bool,
+    ) {
+        let metric = Metric::from_apdex(threshold, zone);
+        self.add(name, scope, metric, forced);
+    }
+
+    pub(crate) fn add(&mut self, name: &str, scope: Option<&str>, metric: Metric, forced: bool) {
         use std::collections::hash_map::Entry;
 
         let id = MetricId {
@@ -79,6 +125,21 @@ impl MetricTable {
         self.add_count(crate::metric_names::SUPPORTABILITY_DROPPED, None, 1.0, true);
     }
 
+    /// Renames the metrics by the rules, dropping the ignored ones.
+    pub(crate) fn apply_rules(self, rules: &MetricRules) -> Self {
+        let mut table = Self {
+            start: self.start,
+            max_table_size: self.max_table_size,
+            metrics: HashMap::new(),
+        };
+        for (id, metric) in self.metrics {
+            if let Some(name) = rules.apply(&id.name) {
+                table.add(&name, id.scope.as_deref(), metric, true);
+            }
+        }
+        table
+    }
+
     pub(crate) fn payload(&self, run_id: &AgentRunId) -> CollectorPayload {
         let duration = Instant::now()
             .checked_duration_since(self.start)
@@ -99,7 +160,7 @@ impl MetricTable {
 }
 
 #[derive(Debug, Clone, Copy)]
-struct Metric {
+pub(crate) struct Metric {
     count_satisfied: f64,
     total_tolerated: f64,
     exclusive_failed: f64,
@@ -109,7 +170,7 @@ struct Metric {
 }
 
 impl Metric {
-    fn merge(self, other: Metric) -> Metric {
+    pub(crate) fn merge(self, other: Metric) -> Metric {
         Self {
             count_satisfied: self.count_satisfied + other.count_satisfied,
             total_tolerated: self.total_tolerated + other.total_tolerated,
@@ -119,7 +180,7 @@ impl Metric {
             sum_squares: self.sum_squares + other.sum_squares,
         }
     }
-    fn from_duration(duration: Duration, exclusive: Duration) -> Self {
+    pub(crate) fn from_duration(duration: Duration, exclusive: Duration) -> Self {
         let ds = duration.as_secs_f64();
         Self {
             count_satisfied: 1.0,
@@ -130,14 +191,56 @@ impl Metric {
             sum_squares: ds * ds,
         }
     }
-    fn from_count(count: f64) -> Self {
-        Self {
-            count_satisfied: count,
+    pub(crate) fn from_value(value: f64) -> Result<Self, MetricError> {
+        if !value.is_finite() {
+            return Err(MetricError::NonFinite);
+        }
+        Ok(Self {
+            count_satisfied: 1.0,
+            total_tolerated: value,
+            exclusive_failed: value,
+            min: value,
+            max: value,
+            sum_squares: value * value,
+        })
+    }
+    pub(crate) fn from_summary(summary: &MetricSummary) -> Result<Self, MetricError> {
+        let values = [summary.total, summary.min, summary.max, summary.sum_squares];
+        if !values.iter().all(|v| v.is_finite()) {
+            return Err(MetricError::NonFinite);
+        }
+        Ok(Self {
+            count_satisfied: summary.count as f64,
+            total_tolerated: summary.total,
+            exclusive_failed: summary.total,
+            min: summary.min,
+            max: summary.max,
+            sum_squares: summary.sum_squares,
+        })
+    }
+    /// The counts of the zones go to the satisfied, tolerated and failed slots.
+    fn from_apdex(threshold: Duration, zone: ApdexZone) -> Self {
+        let threshold = threshold.as_secs_f64();
+        let mut metric = Self {
+            count_satisfied: 0.0,
             total_tolerated: 0.0,
             exclusive_failed: 0.0,
-            min: 0.0,
-            max: 0.0,
+            min: threshold,
+            max: threshold,
             sum_squares: 0.0,
+        };
+        match zone {
+            ApdexZone::Satisfying => metric.count_satisfied = 1.0,
+            ApdexZone::Tolerating => metric.total_tolerated = 1.0,
+            ApdexZone::Failing => metric.exclusive_failed = 1.0,
+        }
+        metric
+    }
+    /// Leaves min/max empty so that merging keeps those of the other values.
+    pub(crate) fn from_count(count: f64) -> Self {
+        Self {
+            count_satisfied: count,
+            ..Self::default()
         }
     }
 }
@@ -154,3 +257,60 @@ impl Default for Metric {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apdex_metric() {
+        let threshold = Duration::from_millis(500);
+        let metric = [
+            ApdexZone::Satisfying,
+            ApdexZone::Satisfying,
+            ApdexZone::Tolerating,
+            ApdexZone::Failing,
+        ]
+        .iter()
+        .map(|&zone| Metric::from_apdex(threshold, zone))
+        .fold(Metric::default(), Metric::merge);
+        let value = MetricValue::from(metric);
+        assert_eq!(value.count_satisfied, 2.0);
+        assert_eq!(value.total_tolerated, 1.0);
+        assert_eq!(value.exclusive_failed, 1.0);
+        assert_eq!(value.min, 0.5);
+        assert_eq!(value.max, 0.5);
+    }
+
+    #[test]
+    fn test_custom_metric_merge() {
+        let metric = Metric::merge(
+            Metric::from_value(3.0).unwrap(),
+            Metric::from_summary(&MetricSummary::new(2, 5.0, 1.0, 4.0, 17.0)).unwrap(),
+        );
+        let value = MetricValue::from(metric);
+        assert_eq!(value.count_satisfied, 3.0);
+        assert_eq!(value.total_tolerated, 8.0);
+        assert_eq!(value.min, 1.0);
+        assert_eq!(value.max, 4.0);
+        assert_eq!(value.sum_squares, 26.0);
+        assert_eq!(
+            Metric::from_value(f64::NAN).unwrap_err(),
+            MetricError::NonFinite
+        );
+    }
+
+    #[test]
+    fn test_custom_metric_count_merge() {
+        let value = MetricValue::from(Metric::from_count(2.0));
+        assert_eq!(value.count_satisfied, 2.0);
+        assert_eq!(value.min, 0.0);
+        assert_eq!(value.max, 0.0);
+
+        let metric = Metric::merge(Metric::from_count(2.0), Metric::from_value(3.0).unwrap());
+        let value = MetricValue::from(metric);
+        assert_eq!(value.count_satisfied, 3.0);
+        assert_eq!(value.min, 3.0);
+        assert_eq!(value.max, 3.0);
+    }
+}
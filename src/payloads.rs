@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 pub(crate) mod analytics_events;
+pub(crate) mod errors;
 pub(crate) mod metrics;
+pub(crate) mod span_events;
 pub(crate) mod transaction_trace;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
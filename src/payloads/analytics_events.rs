@@ -6,15 +6,16 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use crate::apdex::ApdexZone;
 use crate::domain_defs::AgentRunId;
 use crate::payloads::{AgentAttrs, UserAttrs};
+use crate::priority::Priority;
 
 #[derive(Debug, Clone)]
-pub(crate) struct CollectorPayload {
+pub(crate) struct CollectorPayload<E = AnalyticsEventWithAttrs> {
     pub(crate) agent_run_id: AgentRunId,
     pub(crate) properties: Properties,
-    pub(crate) events: Vec<AnalyticsEventWithAttrs>,
+    pub(crate) events: Vec<E>,
 }
 
-impl Serialize for CollectorPayload {
+impl<E: Serialize> Serialize for CollectorPayload<E> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -29,7 +30,7 @@ impl Serialize for CollectorPayload {
     }
 }
 
-impl<'de> Deserialize<'de> for CollectorPayload {
+impl<'de, E: Deserialize<'de>> Deserialize<'de> for CollectorPayload<E> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
@@ -85,10 +86,53 @@ impl<'de> Deserialize<'de> for AnalyticsEventWithAttrs {
     }
 }
 
+/// A custom event, whose type is given by the user.
+#[derive(Debug, Clone)]
+pub(crate) struct CustomEvent {
+    pub(crate) intrinsics: CustomEventIntrinsics,
+    pub(crate) user_attrs: UserAttrs,
+}
+
+impl Serialize for CustomEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        let mut tup = serializer.serialize_tuple(3)?;
+        tup.serialize_element(&self.intrinsics)?;
+        tup.serialize_element(&self.user_attrs)?;
+        tup.serialize_element(&AgentAttrs::default())?;
+        tup.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tup = <(_, _, AgentAttrs)>::deserialize(deserializer)?;
+        Ok(Self {
+            intrinsics: tup.0,
+            user_attrs: tup.1,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct CustomEventIntrinsics {
+    #[serde(rename = "type")]
+    pub(crate) event_type: String,
+    pub(crate) timestamp: f64,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub(crate) enum AnalyticsEvent {
     Transaction(TransactionEvent),
+    TransactionError(ErrorEvent),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -103,6 +147,78 @@ pub(crate) struct TransactionEvent {
     pub(crate) shared: TransactionShared,
     #[serde(rename = "totalTime")]
     pub(crate) total_time: f64,
+    #[serde(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) distributed_trace: Option<DistributedTraceIntrinsics>,
+    #[serde(flatten)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cross_process: Option<Box<CrossProcessIntrinsics>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct DistributedTraceIntrinsics {
+    #[serde(rename = "parent.type")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_type: Option<String>,
+    #[serde(rename = "parent.app")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_app: Option<String>,
+    #[serde(rename = "parent.account")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_account: Option<String>,
+    #[serde(rename = "parent.transportType")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_transport_type: Option<String>,
+    #[serde(rename = "parent.transportDuration")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_transport_duration: Option<f64>,
+    /// The transaction id of the caller.
+    #[serde(rename = "parentId")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_id: Option<String>,
+    #[serde(rename = "parentSpanId")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_span_id: Option<String>,
+    pub(crate) guid: String,
+    #[serde(rename = "traceId")]
+    pub(crate) trace_id: String,
+    pub(crate) priority: Priority,
+    pub(crate) sampled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct CrossProcessIntrinsics {
+    #[serde(rename = "nr.guid")]
+    pub(crate) guid: String,
+    #[serde(rename = "nr.tripId")]
+    pub(crate) trip_id: String,
+    #[serde(rename = "nr.pathHash")]
+    pub(crate) path_hash: String,
+    #[serde(rename = "nr.referringPathHash")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) referring_path_hash: Option<String>,
+    #[serde(rename = "nr.referringTransactionGuid")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) referring_txn_guid: Option<String>,
+    #[serde(rename = "nr.alternatePathHashes")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) alternate_path_hashes: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct ErrorEvent {
+    #[serde(rename = "error.class")]
+    pub(crate) class: String,
+    #[serde(rename = "error.message")]
+    pub(crate) message: String,
+    #[serde(rename = "error.expected")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) expected: bool,
+    pub(crate) timestamp: f64,
+    #[serde(rename = "transactionName")]
+    pub(crate) transaction_name: String,
+    #[serde(flatten)]
+    pub(crate) shared: Option<TransactionShared>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
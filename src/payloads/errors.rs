@@ -0,0 +1,115 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::domain_defs::AgentRunId;
+use crate::payloads::transaction_trace::Intrinsics;
+use crate::payloads::{AgentAttrs, UserAttrs};
+
+#[derive(Debug, Clone)]
+pub(crate) struct CollectorPayload {
+    pub(crate) agent_run_id: AgentRunId,
+    pub(crate) errors: Vec<TracedError>,
+}
+
+impl CollectorPayload {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl Serialize for CollectorPayload {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        let mut tup = serializer.serialize_tuple(2)?;
+        tup.serialize_element(&self.agent_run_id)?;
+        tup.serialize_element(&self.errors)?;
+        tup.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for CollectorPayload {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tup = <(_, _)>::deserialize(deserializer)?;
+        Ok(Self {
+            agent_run_id: tup.0,
+            errors: tup.1,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TracedError {
+    // timestamp (millis)
+    pub(crate) timestamp: f64,
+    // final name
+    pub(crate) name: String,
+    pub(crate) message: String,
+    pub(crate) class: String,
+    pub(crate) params: ErrorParams,
+}
+
+impl Serialize for TracedError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        let mut tup = serializer.serialize_tuple(5)?;
+        tup.serialize_element(&self.timestamp)?;
+        tup.serialize_element(&self.name)?;
+        tup.serialize_element(&self.message)?;
+        tup.serialize_element(&self.class)?;
+        tup.serialize_element(&self.params)?;
+        tup.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for TracedError {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tup = <(_, _, _, _, _)>::deserialize(deserializer)?;
+        Ok(Self {
+            timestamp: tup.0,
+            name: tup.1,
+            message: tup.2,
+            class: tup.3,
+            params: tup.4,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ErrorParams {
+    pub(crate) agent_attributes: AgentAttrs,
+    pub(crate) user_attributes: UserAttrs,
+    pub(crate) intrinsics: Intrinsics,
+    #[serde(rename = "stack_trace")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stack_trace: Option<Vec<StackFrame>>,
+    #[serde(rename = "request_uri")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) request_uri: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub(crate) struct StackFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) filepath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) line: Option<u32>,
+}
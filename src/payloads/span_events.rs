@@ -0,0 +1,138 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::payloads::{AgentAttrs, UserAttrs};
+use crate::priority::Priority;
+
+#[derive(Debug, Clone)]
+pub(crate) struct SpanEvent {
+    pub(crate) intrinsics: SpanIntrinsics,
+    pub(crate) user_attrs: UserAttrs,
+    pub(crate) agent_attrs: AgentAttrs,
+}
+
+impl Serialize for SpanEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeTuple;
+
+        let mut tup = serializer.serialize_tuple(3)?;
+        tup.serialize_element(&self.intrinsics)?;
+        tup.serialize_element(&self.user_attrs)?;
+        tup.serialize_element(&self.agent_attrs)?;
+        tup.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SpanEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tup = <(_, _, _)>::deserialize(deserializer)?;
+        Ok(Self {
+            intrinsics: tup.0,
+            user_attrs: tup.1,
+            agent_attrs: tup.2,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename = "Span")]
+pub(crate) struct SpanIntrinsics {
+    #[serde(rename = "traceId")]
+    pub(crate) trace_id: String,
+    pub(crate) guid: String,
+    #[serde(rename = "parentId")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) parent_id: Option<String>,
+    #[serde(rename = "transactionId")]
+    pub(crate) transaction_id: String,
+    pub(crate) sampled: bool,
+    pub(crate) priority: Priority,
+    /// Milliseconds since the epoch
+    pub(crate) timestamp: i64,
+    /// Seconds
+    pub(crate) duration: f64,
+    pub(crate) name: String,
+    pub(crate) category: SpanCategory,
+    #[serde(rename = "nr.entryPoint")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) entry_point: bool,
+    #[serde(rename = "span.kind")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) component: Option<String>,
+    #[serde(rename = "trustedParentId")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trusted_parent_id: Option<String>,
+    /// Comma-separated keys of the other vendors in `tracestate`.
+    #[serde(rename = "tracingVendors")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tracing_vendors: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SpanCategory {
+    #[default]
+    Generic,
+    Http,
+    Datastore,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_span_event() {
+        let event = SpanEvent {
+            intrinsics: SpanIntrinsics {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_owned(),
+                guid: "27ddd2d8890283b4".to_owned(),
+                parent_id: None,
+                transaction_id: "5569065a5b1313bd".to_owned(),
+                sampled: true,
+                priority: Priority(1.5),
+                timestamp: 1_518_469_636_025,
+                duration: 0.5,
+                name: "WebTransaction/Go/hello".to_owned(),
+                category: SpanCategory::Generic,
+                entry_point: true,
+                kind: None,
+                component: None,
+                trusted_parent_id: None,
+                tracing_vendors: None,
+            },
+            user_attrs: UserAttrs::default(),
+            agent_attrs: AgentAttrs::default(),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!([
+                {
+                    "type": "Span",
+                    "traceId": "4bf92f3577b34da6a3ce929d0e0e4736",
+                    "guid": "27ddd2d8890283b4",
+                    "transactionId": "5569065a5b1313bd",
+                    "sampled": true,
+                    "priority": 1.5,
+                    "timestamp": 1_518_469_636_025i64,
+                    "duration": 0.5,
+                    "name": "WebTransaction/Go/hello",
+                    "category": "generic",
+                    "nr.entryPoint": true,
+                },
+                {},
+                {},
+            ])
+        );
+    }
+}
@@ -198,12 +198,26 @@ impl<'de> Deserialize<'de> for Node {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub(crate) struct NodeAttrs {
     // pub(crate) backtrace: Option<()>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) exclusive_duration_millis: Option<f64>,
-    // pub(crate) transaction_guid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) query: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) port_path_or_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) database_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) uri: Option<String>,
+    #[serde(rename = "http.statusCode")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) http_status_code: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) transaction_guid: Option<String>,
     // #[serde(flatten)]
     // pub(crate) other: HashMap<String, serde_json::Value>,
 }
@@ -220,5 +234,9 @@ pub(crate) struct Properties {
 pub(crate) struct Intrinsics {
     #[serde(rename = "totalTime")]
     pub(crate) total_time: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) trip_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) path_hash: Option<String>,
     // TODO: other intrinsics
 }
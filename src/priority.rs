@@ -0,0 +1,41 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// The priority of an event used in the reservoir sampling.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Priority(pub(crate) f32);
+
+impl Priority {
+    pub(crate) fn new() -> Self {
+        Self::from_random(rand::random::<f32>())
+    }
+
+    fn from_random(x: f32) -> Self {
+        // Truncate to 6 decimal places so that the value is the same across agents.
+        Self((x * 1e6).trunc() / 1e6)
+    }
+
+    /// Raises the priority of a sampled trace above the unsampled ones.
+    pub(crate) fn boost(self) -> Self {
+        Self(self.0 + 1.0)
+    }
+
+    pub(crate) fn total_cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_truncation() {
+        assert_eq!(Priority::from_random(0.123_456_78).0, 0.123_456);
+        assert_eq!(Priority::from_random(0.0).0, 0.0);
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use http::HeaderMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const X_REQUEST_START: &str = "X-Request-Start";
+const X_QUEUE_START: &str = "X-Queue-Start";
+
+/// 2000-01-01T00:00:00Z, used to guess the unit of the timestamp.
+const EARLIEST_ACCEPTABLE_SECONDS: f64 = 946_684_800.0;
+
+/// Parses a timestamp like `t=1600000000.123`, given in seconds, milliseconds or microseconds.
+fn parse_queue_time(s: &str) -> Option<SystemTime> {
+    let s = s.trim();
+    let s = s.strip_prefix("t=").unwrap_or(s);
+    let f = s.parse::<f64>().ok()?;
+    [1e6, 1e3, 1.0]
+        .iter()
+        .map(|unit| f / unit)
+        .find(|&secs| secs > EARLIEST_ACCEPTABLE_SECONDS)
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .map(|since_epoch| UNIX_EPOCH + since_epoch)
+}
+
+/// Computes how long the request waited before the transaction started.
+pub(crate) fn queue_duration(headers: &HeaderMap, txn_start: SystemTime) -> Option<Duration> {
+    let header = headers
+        .get(X_QUEUE_START)
+        .or_else(|| headers.get(X_REQUEST_START))?;
+    let queue_start = parse_queue_time(header.to_str().ok()?)?;
+    txn_start
+        .duration_since(queue_start)
+        .ok()
+        .filter(|duration| *duration > Duration::from_secs(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_queue_time() {
+        let expected = UNIX_EPOCH + Duration::from_millis(1_600_000_000_500);
+        assert_eq!(parse_queue_time("t=1600000000.5"), Some(expected));
+        assert_eq!(parse_queue_time("t=1600000000500"), Some(expected));
+        assert_eq!(parse_queue_time("1600000000500000"), Some(expected));
+        assert_eq!(parse_queue_time("t=12345"), None);
+        assert_eq!(parse_queue_time("t=-1600000000"), None);
+        assert_eq!(parse_queue_time("t=now"), None);
+    }
+
+    #[test]
+    fn test_queue_duration() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_600_000_001);
+        let mut headers = HeaderMap::new();
+        assert_eq!(queue_duration(&headers, start), None);
+        headers.insert(X_REQUEST_START, "t=1600000000750".parse().unwrap());
+        assert_eq!(
+            queue_duration(&headers, start),
+            Some(Duration::from_millis(250))
+        );
+        headers.insert(X_QUEUE_START, "t=1600000002000".parse().unwrap());
+        assert_eq!(queue_duration(&headers, start), None);
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::connect_reply::{MetricRule, SegmentRule};
+
+/// Name normalization rules sent from the server: `url_rules`, `transaction_name_rules` or
+/// `metric_name_rules`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetricRules {
+    /// Sorted by `eval_order`.
+    rules: Vec<CompiledRule>,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    re: Regex,
+    replacement: String,
+    ignore: bool,
+    each_segment: bool,
+    replace_all: bool,
+    terminate_chain: bool,
+    eval_order: i32,
+}
+
+enum RuleResult {
+    Unmatched,
+    Matched(String),
+    Ignore,
+}
+
+impl MetricRules {
+    pub(crate) fn new(rules: &[MetricRule]) -> Self {
+        let mut rules = rules
+            .iter()
+            .filter_map(|rule| {
+                let re = match Regex::new(&format!("(?i){}", rule.match_expression)) {
+                    Ok(re) => re,
+                    Err(e) => {
+                        log::warn!("invalid metric rule {:?}: {}", rule.match_expression, e);
+                        return None;
+                    }
+                };
+                Some(CompiledRule {
+                    re,
+                    replacement: convert_replacement(&rule.replacement),
+                    ignore: rule.ignore,
+                    each_segment: rule.each_segment,
+                    replace_all: rule.replace_all,
+                    terminate_chain: rule.terminate_chain,
+                    eval_order: rule.eval_order,
+                })
+            })
+            .collect::<Vec<_>>();
+        // Stable sort to keep the server order among the same eval_order
+        rules.sort_by_key(|rule| rule.eval_order);
+        Self { rules }
+    }
+
+    /// Applies the rules. Returns `None` if the name should be ignored.
+    pub(crate) fn apply(&self, name: &str) -> Option<String> {
+        let mut name = name.to_owned();
+        for rule in &self.rules {
+            match rule.apply(&name) {
+                RuleResult::Unmatched => {}
+                RuleResult::Matched(replaced) => {
+                    name = replaced;
+                    if rule.terminate_chain {
+                        break;
+                    }
+                }
+                RuleResult::Ignore => return None,
+            }
+        }
+        Some(name)
+    }
+}
+
+impl CompiledRule {
+    fn apply(&self, name: &str) -> RuleResult {
+        // Ignore rules apply to the whole name, even if each_segment is set.
+        if self.ignore {
+            return if self.re.is_match(name) {
+                RuleResult::Ignore
+            } else {
+                RuleResult::Unmatched
+            };
+        }
+        if self.each_segment {
+            let mut matched = false;
+            let segments = name
+                .split('/')
+                .map(|segment| {
+                    let replaced = self.replace(segment);
+                    matched |= replaced.is_some();
+                    replaced.map(Cow::Owned).unwrap_or(Cow::Borrowed(segment))
+                })
+                .collect::<Vec<_>>();
+            if matched {
+                RuleResult::Matched(segments.join("/"))
+            } else {
+                RuleResult::Unmatched
+            }
+        } else if let Some(replaced) = self.replace(name) {
+            RuleResult::Matched(replaced)
+        } else {
+            RuleResult::Unmatched
+        }
+    }
+
+    fn replace(&self, s: &str) -> Option<String> {
+        if !self.re.is_match(s) {
+            return None;
+        }
+        let replaced = if self.replace_all {
+            self.re.replace_all(s, self.replacement.as_str())
+        } else {
+            self.re.replace(s, self.replacement.as_str())
+        };
+        Some(replaced.into_owned())
+    }
+}
+
+/// Allow-lists of the segments of transaction names, sent as `transaction_segment_terms`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SegmentTerms {
+    /// Keyed by the prefix consisting of two segments, such as `WebTransaction/Uri`.
+    rules: HashMap<String, HashSet<String>>,
+}
+
+impl SegmentTerms {
+    pub(crate) fn new(rules: &[SegmentRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| {
+                let prefix = rule.prefix.strip_suffix('/').unwrap_or(&rule.prefix);
+                if prefix.split('/').count() != 2 {
+                    log::warn!("invalid segment term prefix: {:?}", rule.prefix);
+                    return None;
+                }
+                Some((prefix.to_owned(), rule.terms.iter().cloned().collect()))
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Replaces the segments not in the allow-list with `*`, collapsing consecutive ones.
+    pub(crate) fn apply(&self, name: &str) -> String {
+        let mut parts = name.splitn(3, '/');
+        let (first, second, rest) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(first), Some(second), Some(rest)) if !rest.is_empty() => (first, second, rest),
+            _ => return name.to_owned(),
+        };
+        let terms = if let Some(terms) = self.rules.get(&format!("{}/{}", first, second)) {
+            terms
+        } else {
+            return name.to_owned();
+        };
+        let mut segments = Vec::<&str>::new();
+        for segment in rest.split('/') {
+            if terms.contains(segment) {
+                segments.push(segment);
+            } else if segments.last() != Some(&"*") {
+                segments.push("*");
+            }
+        }
+        format!("{}/{}/{}", first, second, segments.join("/"))
+    }
+}
+
+/// Converts `\1` style back-references to `${1}` understood by the `regex` crate.
+fn convert_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                converted.push_str("${");
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    converted.push(d);
+                    chars.next();
+                }
+                converted.push('}');
+            }
+            '$' => converted.push_str("$$"),
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(match_expression: &str, replacement: &str) -> MetricRule {
+        MetricRule {
+            ignore: false,
+            each_segment: false,
+            replace_all: false,
+            terminate_chain: false,
+            eval_order: 0,
+            replacement: replacement.to_owned(),
+            match_expression: match_expression.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_segment_terms() {
+        let terms = SegmentTerms::new(&[
+            SegmentRule {
+                prefix: "WebTransaction/Uri/".to_owned(),
+                terms: vec!["users".to_owned(), "posts".to_owned()],
+            },
+            SegmentRule {
+                prefix: "Invalid".to_owned(),
+                terms: vec![],
+            },
+        ]);
+        assert_eq!(
+            terms.apply("WebTransaction/Uri/users/123/abc/posts/456"),
+            "WebTransaction/Uri/users/*/posts/*"
+        );
+        assert_eq!(
+            terms.apply("WebTransaction/Uri/a/b/users"),
+            "WebTransaction/Uri/*/users"
+        );
+        assert_eq!(
+            terms.apply("WebTransaction/Go/users/123"),
+            "WebTransaction/Go/users/123"
+        );
+        assert_eq!(terms.apply("WebTransaction/Uri"), "WebTransaction/Uri");
+        assert_eq!(terms.apply("WebTransaction/Uri/"), "WebTransaction/Uri/");
+    }
+
+    #[test]
+    fn test_convert_replacement() {
+        assert_eq!(convert_replacement(r"\1/*"), "${1}/*");
+        assert_eq!(convert_replacement(r"a\12$b"), "a${12}$$b");
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = MetricRules::new(&[
+            MetricRule {
+                eval_order: 1,
+                ..rule(r"^(WebTransaction/Go/users)/\d+$", r"\1/*")
+            },
+            MetricRule {
+                eval_order: 2,
+                ignore: true,
+                ..rule(r"/health$", "")
+            },
+            MetricRule {
+                eval_order: 0,
+                each_segment: true,
+                ..rule(r"^[0-9a-f]{32}$", "*")
+            },
+        ]);
+        assert_eq!(
+            rules.apply("WebTransaction/Go/users/123").as_deref(),
+            Some("WebTransaction/Go/users/*")
+        );
+        assert_eq!(
+            rules
+                .apply("WebTransaction/Go/files/0123456789abcdef0123456789ABCDEF/raw")
+                .as_deref(),
+            Some("WebTransaction/Go/files/*/raw")
+        );
+        assert_eq!(rules.apply("WebTransaction/Go/health"), None);
+        assert_eq!(
+            rules.apply("WebTransaction/Go/other").as_deref(),
+            Some("WebTransaction/Go/other")
+        );
+    }
+
+    #[test]
+    fn test_replace_all_and_terminate() {
+        let rules = MetricRules::new(&[
+            MetricRule {
+                eval_order: 0,
+                replace_all: true,
+                terminate_chain: true,
+                ..rule(r"\d+", "N")
+            },
+            MetricRule {
+                eval_order: 1,
+                ..rule(r"N", "X")
+            },
+        ]);
+        assert_eq!(rules.apply("a1/b22/c").as_deref(), Some("aN/bN/c"));
+        assert_eq!(rules.apply("N/c").as_deref(), Some("X/c"));
+
+        let rules = MetricRules::new(&[rule(r"\d+", "N")]);
+        assert_eq!(rules.apply("a1/b22/c").as_deref(), Some("aN/b22/c"));
+    }
+}
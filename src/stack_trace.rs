@@ -0,0 +1,163 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+
+use crate::payloads::errors::StackFrame;
+
+const MAX_STACK_TRACE_FRAMES: usize = 100;
+
+/// Converts a captured backtrace into stack frames.
+///
+/// `Backtrace` doesn't expose its frames on stable Rust, so its `Display` output is parsed.
+/// The frames of the backtrace machinery and this crate are skipped.
+pub(crate) fn stack_trace(backtrace: &Backtrace) -> Option<Vec<StackFrame>> {
+    stack_trace_skipping(backtrace, is_agent_frame)
+}
+
+/// Same as [`stack_trace`], but also skips the frames of the panic machinery.
+pub(crate) fn panic_stack_trace(backtrace: &Backtrace) -> Option<Vec<StackFrame>> {
+    stack_trace_skipping(backtrace, |name| {
+        is_agent_frame(name) || is_panic_frame(name)
+    })
+}
+
+fn stack_trace_skipping(
+    backtrace: &Backtrace,
+    skip: impl Fn(&str) -> bool,
+) -> Option<Vec<StackFrame>> {
+    if backtrace.status() != BacktraceStatus::Captured {
+        return None;
+    }
+    Some(skip_frames(parse_backtrace(&backtrace.to_string()), skip))
+}
+
+fn skip_frames(frames: Vec<StackFrame>, skip: impl Fn(&str) -> bool) -> Vec<StackFrame> {
+    frames
+        .into_iter()
+        .skip_while(|frame| frame.name.as_deref().is_some_and(&skip))
+        .take(MAX_STACK_TRACE_FRAMES)
+        .collect()
+}
+
+fn is_agent_frame(name: &str) -> bool {
+    name.starts_with("std::backtrace::") || name.starts_with("newrelic_unofficial::")
+}
+
+fn is_panic_frame(name: &str) -> bool {
+    const PANIC_FRAME_PREFIXES: &[&str] = &[
+        "std::panicking::",
+        "core::panicking::",
+        "std::sys::backtrace::",
+        "std::sys_common::backtrace::",
+        "rust_begin_unwind",
+        "__rustc::rust_begin_unwind",
+        "<alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call",
+    ];
+    PANIC_FRAME_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+fn parse_backtrace(s: &str) -> Vec<StackFrame> {
+    let mut frames = Vec::<StackFrame>::new();
+    for line in s.lines() {
+        let line = line.trim();
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                // The location is `<path>:<line>:<column>`.
+                let mut parts = location.rsplitn(3, ':');
+                let _column = parts.next();
+                let line = parts.next().and_then(|line| line.parse::<u32>().ok());
+                if let (Some(line), Some(path)) = (line, parts.next()) {
+                    frame.filepath = Some(path.to_owned());
+                    frame.line = Some(line);
+                } else {
+                    frame.filepath = Some(location.to_owned());
+                }
+            }
+        } else if let Some(pos) = line.find(": ") {
+            if line[..pos].chars().all(|c| c.is_ascii_digit()) {
+                frames.push(StackFrame {
+                    name: Some(line[pos + 2..].to_owned()),
+                    filepath: None,
+                    line: None,
+                });
+            }
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backtrace() {
+        let s = "   0: std::backtrace::Backtrace::capture
+             at /rustc/library/std/src/backtrace.rs:296:9
+   1: myapp::handler::{{closure}}
+             at ./src/handler.rs:12:5
+   2: main
+   3: <unknown>
+";
+        let frames = parse_backtrace(s);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(
+            frames[0].name.as_deref(),
+            Some("std::backtrace::Backtrace::capture")
+        );
+        assert_eq!(
+            frames[1].name.as_deref(),
+            Some("myapp::handler::{{closure}}")
+        );
+        assert_eq!(frames[1].filepath.as_deref(), Some("./src/handler.rs"));
+        assert_eq!(frames[1].line, Some(12));
+        assert_eq!(frames[2].name.as_deref(), Some("main"));
+        assert_eq!(frames[2].filepath, None);
+    }
+
+    #[test]
+    fn test_skip_panic_frames() {
+        let s = "   0: std::backtrace::Backtrace::capture
+   1: newrelic_unofficial::ApplicationInner::notice_panic
+   2: <alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call
+   3: std::panicking::rust_panic_with_hook
+   4: std::panicking::begin_panic_handler::{{closure}}
+   5: std::sys::backtrace::__rust_end_short_backtrace
+   6: rust_begin_unwind
+   7: core::panicking::panic_fmt
+   8: core::option::unwrap_failed
+   9: myapp::handler
+  10: std::panicking::try
+";
+        let frames = skip_frames(parse_backtrace(s), |name| {
+            is_agent_frame(name) || is_panic_frame(name)
+        });
+        let names = frames
+            .iter()
+            .map(|frame| frame.name.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "core::option::unwrap_failed",
+                "myapp::handler",
+                "std::panicking::try"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disabled_backtrace() {
+        assert!(stack_trace(&Backtrace::disabled()).is_none());
+    }
+
+    #[test]
+    fn test_captured_backtrace() {
+        let frames = stack_trace(&Backtrace::force_capture()).unwrap();
+        assert!(!frames.is_empty());
+        assert!(frames.len() <= MAX_STACK_TRACE_FRAMES);
+    }
+}
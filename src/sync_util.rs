@@ -1,4 +1,5 @@
 use parking_lot::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
@@ -10,6 +11,7 @@ pub(crate) struct ShutdownError;
 pub(crate) struct Shutdown {
     shutdown: Mutex<bool>,
     cond: Condvar,
+    harvest_requested: AtomicBool,
 }
 
 impl Shutdown {
@@ -17,6 +19,7 @@ impl Shutdown {
         Self {
             shutdown: Mutex::new(false),
             cond: Condvar::new(),
+            harvest_requested: AtomicBool::new(false),
         }
     }
 
@@ -26,6 +29,15 @@ impl Shutdown {
         self.cond.notify_all();
     }
 
+    /// Asks the harvest thread to send the collected data at its next wakeup. It never blocks.
+    pub(crate) fn request_harvest(&self) {
+        self.harvest_requested.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn take_harvest_request(&self) -> bool {
+        self.harvest_requested.swap(false, Ordering::SeqCst)
+    }
+
     pub(crate) fn sleep(&self, duration: Duration) -> Result<(), ShutdownError> {
         let timeout = Instant::now() + duration;
         let mut shutdown = self.shutdown.lock();
@@ -0,0 +1,729 @@
+// Copyright 2020 New Relic Corporation. (for the original go-agent)
+// Copyright 2020 Masaki Hara.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::cross_process::AppData;
+use crate::distributed_tracing::new_guid;
+use crate::limits::{MAX_SPAN_EVENTS, MAX_TXN_TRACE_NODES};
+use crate::metric_names;
+use crate::metrics::{Metric, MetricTable};
+use crate::payloads::span_events::SpanCategory;
+use crate::payloads::transaction_trace::{Node, NodeAttrs};
+use crate::payloads::AgentAttrs;
+use crate::segments::{
+    safe_url, DatastoreMetricKey, DatastoreSegment, ExternalMetricKey, ExternalSegment,
+    MessageProducerSegment,
+};
+
+/// Identifies a started segment. It is invalidated once the segment (or one of its ancestors) ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SegmentStartTime {
+    stamp: u64,
+    depth: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SegmentEnd {
+    pub(crate) duration: Duration,
+    pub(crate) exclusive: Duration,
+}
+
+/// What a segment adds to its span event.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpanAttrs {
+    pub(crate) category: SpanCategory,
+    pub(crate) component: Option<String>,
+    pub(crate) agent_attrs: AgentAttrs,
+}
+
+/// A span event of an ended segment, lacking the transaction-wide fields.
+#[derive(Debug, Clone)]
+pub(crate) struct SpanData {
+    pub(crate) guid: String,
+    pub(crate) parent_id: String,
+    pub(crate) name: String,
+    pub(crate) start: Instant,
+    pub(crate) duration: Duration,
+    pub(crate) attrs: SpanAttrs,
+}
+
+#[derive(Debug)]
+pub(crate) struct Tracer {
+    start: Instant,
+    segment_threshold: Duration,
+    stamp: u64,
+    threads: Vec<Thread>,
+    num_nodes: usize,
+    /// The sum of the exclusive durations of the ended segments in all threads.
+    segments_exclusive: Duration,
+    /// The span id of the transaction. Spans are collected only if it is present.
+    root_span_id: Option<String>,
+    spans: Vec<SpanData>,
+    custom_segments: HashMap<String, Metric>,
+    datastore_segments: HashMap<DatastoreMetricKey, Metric>,
+    datastore_call_count: u64,
+    datastore_duration: Duration,
+    external_segments: HashMap<ExternalMetricKey, Metric>,
+    external_call_count: u64,
+    external_duration: Duration,
+    message_segments: HashMap<String, Metric>,
+}
+
+impl Tracer {
+    pub(crate) fn new(now: Instant, segment_threshold: Duration, collect_spans: bool) -> Self {
+        Self {
+            start: now,
+            segment_threshold,
+            stamp: 0,
+            threads: vec![Thread::new()],
+            num_nodes: 0,
+            segments_exclusive: Duration::from_secs(0),
+            root_span_id: if collect_spans {
+                Some(new_guid())
+            } else {
+                None
+            },
+            spans: vec![],
+            custom_segments: HashMap::new(),
+            datastore_segments: HashMap::new(),
+            datastore_call_count: 0,
+            datastore_duration: Duration::from_secs(0),
+            external_segments: HashMap::new(),
+            external_call_count: 0,
+            external_duration: Duration::from_secs(0),
+            message_segments: HashMap::new(),
+        }
+    }
+
+    /// Adds a thread with its own segment stack and returns its id.
+    pub(crate) fn new_thread(&mut self) -> usize {
+        self.threads.push(Thread::new());
+        self.threads.len() - 1
+    }
+
+    pub(crate) fn start_segment(&mut self, thread_id: usize, now: Instant) -> SegmentStartTime {
+        self.stamp += 1;
+        let stamp = self.stamp;
+        let thread = &mut self.threads[thread_id];
+        let depth = thread.stack.len();
+        let span_id = self.root_span_id.as_ref().map(|_| new_guid());
+        thread.stack.push(SegmentFrame {
+            stamp,
+            span_id,
+            start: now,
+            children: Duration::from_secs(0),
+            nodes: vec![],
+        });
+        SegmentStartTime { stamp, depth }
+    }
+
+    /// Pops the segment (and any segment left open above it) and records its trace node.
+    ///
+    /// Returns `None` if the segment has already been ended or was abandoned.
+    pub(crate) fn end_segment(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        name: String,
+        attrs: NodeAttrs,
+    ) -> Option<SegmentEnd> {
+        self.end_segment_with_span(thread_id, start, now, name, attrs, SpanAttrs::default())
+    }
+
+    /// Same as `end_segment`, but specifies the attributes of the span event.
+    pub(crate) fn end_segment_with_span(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        name: String,
+        attrs: NodeAttrs,
+        span_attrs: SpanAttrs,
+    ) -> Option<SegmentEnd> {
+        let thread = &mut self.threads[thread_id];
+        if start.depth >= thread.stack.len() || thread.stack[start.depth].stamp != start.stamp {
+            return None;
+        }
+        let segment_start = thread.stack[start.depth].start;
+        let span_id = thread.stack[start.depth].span_id.take();
+        // Segments left open above this one are abandoned; their children are kept.
+        let mut nodes = vec![];
+        let mut children = Duration::from_secs(0);
+        for frame in thread.stack.drain(start.depth..) {
+            children += frame.children;
+            nodes.extend(frame.nodes);
+        }
+        let stop = now.max(segment_start);
+        let duration = stop - segment_start;
+        let exclusive = duration.checked_sub(children).unwrap_or_default();
+        self.segments_exclusive += exclusive;
+
+        if let (Some(span_id), Some(root_span_id)) = (span_id, &self.root_span_id) {
+            if self.spans.len() < MAX_SPAN_EVENTS as usize {
+                let parent_id = thread
+                    .stack
+                    .last()
+                    .and_then(|parent| parent.span_id.clone())
+                    .unwrap_or_else(|| root_span_id.clone());
+                self.spans.push(SpanData {
+                    guid: span_id,
+                    parent_id,
+                    name: name.clone(),
+                    start: segment_start,
+                    duration,
+                    attrs: span_attrs,
+                });
+            }
+        }
+
+        let (parent_children, parent_nodes) = if let Some(parent) = thread.stack.last_mut() {
+            (&mut parent.children, &mut parent.nodes)
+        } else {
+            (&mut thread.children, &mut thread.nodes)
+        };
+        *parent_children += duration;
+        if duration >= self.segment_threshold && self.num_nodes < MAX_TXN_TRACE_NODES {
+            self.num_nodes += 1;
+            parent_nodes.push(Node {
+                relative_start_millis: relative_millis(self.start, segment_start),
+                relative_stop_millis: relative_millis(self.start, stop),
+                name,
+                attrs: NodeAttrs {
+                    exclusive_duration_millis: Some(exclusive.as_secs_f64() * 1000.0),
+                    ..attrs
+                },
+                children: nodes,
+            });
+        } else {
+            // Keep the descendants under the nearest recorded ancestor.
+            parent_nodes.extend(nodes);
+        }
+
+        Some(SegmentEnd {
+            duration,
+            exclusive,
+        })
+    }
+
+    pub(crate) fn end_basic_segment(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        name: &str,
+    ) {
+        let metric_name = metric_names::custom_segment_metric(name);
+        let end = self.end_segment(
+            thread_id,
+            start,
+            now,
+            metric_name.clone(),
+            NodeAttrs::default(),
+        );
+        if let Some(end) = end {
+            add_metric(&mut self.custom_segments, metric_name, end);
+        }
+    }
+
+    pub(crate) fn end_datastore_segment(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        segment: &DatastoreSegment,
+    ) {
+        let key = segment.metric_key();
+        let attrs = NodeAttrs {
+            query: segment.parameterized_query.clone(),
+            host: segment.host.clone(),
+            port_path_or_id: segment.port_path_or_id.clone(),
+            database_name: segment.database_name.clone(),
+            ..NodeAttrs::default()
+        };
+        let span_attrs = if self.root_span_id.is_some() {
+            datastore_span_attrs(segment, &key)
+        } else {
+            SpanAttrs::default()
+        };
+        let name = metric_names::datastore_scoped_metric(&key);
+        let end = self.end_segment_with_span(thread_id, start, now, name, attrs, span_attrs);
+        if let Some(end) = end {
+            self.datastore_call_count += 1;
+            self.datastore_duration += end.duration;
+            add_metric(&mut self.datastore_segments, key, end);
+        }
+    }
+
+    pub(crate) fn end_external_segment(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        segment: &ExternalSegment,
+        app_data: Option<AppData>,
+    ) {
+        let mut key = segment.metric_key();
+        let mut transaction_guid = None;
+        if let Some(app_data) = app_data {
+            key.cross_process_id = app_data.cross_process_id;
+            key.transaction_name = app_data.transaction_name;
+            transaction_guid = app_data.transaction_guid;
+        }
+        let attrs = NodeAttrs {
+            uri: Some(safe_url(&segment.uri)),
+            http_status_code: segment.status.map(|status| status.as_u16()),
+            transaction_guid,
+            ..NodeAttrs::default()
+        };
+        let span_attrs = if self.root_span_id.is_some() {
+            external_span_attrs(segment)
+        } else {
+            SpanAttrs::default()
+        };
+        let name = metric_names::external_scoped_metric(&key);
+        let end = self.end_segment_with_span(thread_id, start, now, name, attrs, span_attrs);
+        if let Some(end) = end {
+            self.external_call_count += 1;
+            self.external_duration += end.duration;
+            add_metric(&mut self.external_segments, key, end);
+        }
+    }
+
+    pub(crate) fn end_message_segment(
+        &mut self,
+        thread_id: usize,
+        start: SegmentStartTime,
+        now: Instant,
+        segment: &MessageProducerSegment,
+    ) {
+        let name = segment.metric_name();
+        let end = self.end_segment(thread_id, start, now, name.clone(), NodeAttrs::default());
+        if let Some(end) = end {
+            add_metric(&mut self.message_segments, name, end);
+        }
+    }
+
+    pub(crate) fn datastore_call_count(&self) -> Option<u64> {
+        Some(self.datastore_call_count).filter(|&count| count > 0)
+    }
+
+    pub(crate) fn datastore_duration(&self) -> Option<f64> {
+        self.datastore_call_count()
+            .map(|_| self.datastore_duration.as_secs_f64())
+    }
+
+    pub(crate) fn external_call_count(&self) -> Option<u64> {
+        Some(self.external_call_count).filter(|&count| count > 0)
+    }
+
+    pub(crate) fn external_duration(&self) -> Option<f64> {
+        self.external_call_count()
+            .map(|_| self.external_duration.as_secs_f64())
+    }
+
+    /// Records the metrics of the segments, scoped to the transaction if necessary.
+    pub(crate) fn merge_breakdown_metrics(
+        &self,
+        scope: &str,
+        is_web: bool,
+        metric_table: &mut MetricTable,
+    ) {
+        for (name, &metric) in &self.custom_segments {
+            metric_table.add(name, None, metric, false);
+            metric_table.add(name, Some(scope), metric, false);
+        }
+
+        for (key, &metric) in &self.datastore_segments {
+            metric_table.add(metric_names::DATASTORE_ALL, None, metric, true);
+            let product = metric_names::datastore_product_metric(key);
+            metric_table.add(&product.all, None, metric, true);
+            if is_web {
+                metric_table.add(metric_names::DATASTORE_WEB, None, metric, true);
+                metric_table.add(&product.web, None, metric, true);
+            } else {
+                metric_table.add(metric_names::DATASTORE_OTHER, None, metric, true);
+                metric_table.add(&product.other, None, metric, true);
+            }
+            if !key.host.is_empty() && !key.port_path_or_id.is_empty() {
+                let instance = metric_names::datastore_instance_metric(key);
+                metric_table.add(&instance, None, metric, false);
+            }
+            let operation = metric_names::datastore_operation_metric(key);
+            metric_table.add(&operation, None, metric, false);
+            if !key.collection.is_empty() {
+                let statement = metric_names::datastore_statement_metric(key);
+                metric_table.add(&statement, None, metric, false);
+                metric_table.add(&statement, Some(scope), metric, false);
+            } else {
+                metric_table.add(&operation, Some(scope), metric, false);
+            }
+        }
+
+        for (key, &metric) in &self.external_segments {
+            metric_table.add(metric_names::EXTERNAL_ALL, None, metric, true);
+            if is_web {
+                metric_table.add(metric_names::EXTERNAL_WEB, None, metric, true);
+            } else {
+                metric_table.add(metric_names::EXTERNAL_OTHER, None, metric, true);
+            }
+            let host = metric_names::external_host_metric(key);
+            metric_table.add(&host, None, metric, false);
+            if metric_names::is_cross_process(key) {
+                let app = metric_names::external_app_metric(key);
+                metric_table.add(&app, None, metric, false);
+                let txn = metric_names::external_transaction_metric(key);
+                metric_table.add(&txn, None, metric, false);
+            }
+            let scoped = metric_names::external_scoped_metric(key);
+            metric_table.add(&scoped, Some(scope), metric, false);
+        }
+
+        for (name, &metric) in &self.message_segments {
+            metric_table.add(name, None, metric, false);
+            metric_table.add(name, Some(scope), metric, false);
+        }
+    }
+
+    /// Time spent in the thread outside of any top-level segment.
+    pub(crate) fn root_exclusive(&self, thread_id: usize, duration: Duration) -> Duration {
+        duration
+            .checked_sub(self.threads[thread_id].children)
+            .unwrap_or_default()
+    }
+
+    /// The time spent in the transaction summed over all threads, given the duration of the main
+    /// thread.
+    pub(crate) fn total_time(&self, duration: Duration) -> Duration {
+        self.root_exclusive(0, duration) + self.segments_exclusive
+    }
+
+    pub(crate) fn root_span_id(&self) -> Option<&str> {
+        self.root_span_id.as_deref()
+    }
+
+    /// The span id of the innermost running segment of the thread, or the root span id.
+    pub(crate) fn current_span_id(&self, thread_id: usize) -> Option<&str> {
+        self.threads[thread_id]
+            .stack
+            .last()
+            .and_then(|frame| frame.span_id.as_deref())
+            .or_else(|| self.root_span_id())
+    }
+
+    /// Drops the collected spans and stops collecting them, as for unsampled transactions.
+    pub(crate) fn stop_collecting_spans(&mut self) {
+        self.root_span_id = None;
+        self.spans = vec![];
+    }
+
+    pub(crate) fn take_spans(&mut self) -> Vec<SpanData> {
+        std::mem::take(&mut self.spans)
+    }
+
+    /// Takes the trace nodes of the top-level segments of all threads, the main thread first.
+    pub(crate) fn take_nodes(&mut self) -> Vec<Node> {
+        self.threads
+            .iter_mut()
+            .flat_map(|thread| std::mem::take(&mut thread.nodes))
+            .collect()
+    }
+}
+
+fn add_metric<K: Eq + Hash>(metrics: &mut HashMap<K, Metric>, key: K, end: SegmentEnd) {
+    let metric = Metric::from_duration(end.duration, end.exclusive);
+    metrics
+        .entry(key)
+        .and_modify(|m| *m = m.merge(metric))
+        .or_insert(metric);
+}
+
+fn datastore_span_attrs(segment: &DatastoreSegment, key: &DatastoreMetricKey) -> SpanAttrs {
+    let mut agent_attrs = AgentAttrs::default();
+    let mut add = |key: &str, value: &Option<String>| {
+        if let Some(value) = value {
+            agent_attrs.0.insert(key.to_owned(), value.clone().into());
+        }
+    };
+    add("db.statement", &segment.parameterized_query);
+    add("db.instance", &segment.database_name);
+    add("peer.hostname", &segment.host);
+    if !key.host.is_empty() {
+        add(
+            "peer.address",
+            &Some(format!("{}:{}", key.host, key.port_path_or_id)),
+        );
+    }
+    SpanAttrs {
+        category: SpanCategory::Datastore,
+        component: Some(key.product.clone()),
+        agent_attrs,
+    }
+}
+
+fn external_span_attrs(segment: &ExternalSegment) -> SpanAttrs {
+    let mut agent_attrs = AgentAttrs::default();
+    agent_attrs
+        .0
+        .insert("http.url".to_owned(), safe_url(&segment.uri).into());
+    agent_attrs
+        .0
+        .insert("http.method".to_owned(), segment.method.as_str().into());
+    if let Some(status) = segment.status {
+        agent_attrs
+            .0
+            .insert("http.statusCode".to_owned(), status.as_u16().into());
+    }
+    SpanAttrs {
+        category: SpanCategory::Http,
+        component: Some(segment.library.clone()),
+        agent_attrs,
+    }
+}
+
+fn relative_millis(base: Instant, t: Instant) -> i64 {
+    t.checked_duration_since(base)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[derive(Debug)]
+struct Thread {
+    stack: Vec<SegmentFrame>,
+    /// The total duration of the top-level segments.
+    children: Duration,
+    /// Trace nodes of the finished top-level segments.
+    nodes: Vec<Node>,
+}
+
+impl Thread {
+    fn new() -> Self {
+        Self {
+            stack: vec![],
+            children: Duration::from_secs(0),
+            nodes: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SegmentFrame {
+    stamp: u64,
+    span_id: Option<String>,
+    start: Instant,
+    /// The total duration of the direct children.
+    children: Duration,
+    /// Trace nodes of the finished children.
+    nodes: Vec<Node>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_segments() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut tracer = Tracer::new(start, Duration::from_millis(2), false);
+
+        let outer = tracer.start_segment(0, ms(10));
+        let inner = tracer.start_segment(0, ms(20));
+        let inner_end = tracer
+            .end_segment(
+                0,
+                inner,
+                ms(50),
+                "Custom/inner".to_owned(),
+                NodeAttrs::default(),
+            )
+            .unwrap();
+        assert_eq!(inner_end.duration, Duration::from_millis(30));
+        assert_eq!(inner_end.exclusive, Duration::from_millis(30));
+        let outer_end = tracer
+            .end_segment(
+                0,
+                outer,
+                ms(110),
+                "Custom/outer".to_owned(),
+                NodeAttrs::default(),
+            )
+            .unwrap();
+        assert_eq!(outer_end.duration, Duration::from_millis(100));
+        assert_eq!(outer_end.exclusive, Duration::from_millis(70));
+        assert_eq!(
+            tracer.root_exclusive(0, Duration::from_millis(200)),
+            Duration::from_millis(100)
+        );
+
+        let nodes = tracer.take_nodes();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "Custom/outer");
+        assert_eq!(nodes[0].relative_start_millis, 10);
+        assert_eq!(nodes[0].relative_stop_millis, 110);
+        assert_eq!(nodes[0].children.len(), 1);
+        assert_eq!(nodes[0].children[0].name, "Custom/inner");
+    }
+
+    #[test]
+    fn test_segment_order() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut tracer = Tracer::new(start, Duration::from_millis(2), false);
+
+        let outer = tracer.start_segment(0, ms(0));
+        let inner = tracer.start_segment(0, ms(10));
+        // Ending the parent first abandons the child.
+        assert!(tracer
+            .end_segment(
+                0,
+                outer,
+                ms(20),
+                "Custom/outer".to_owned(),
+                NodeAttrs::default()
+            )
+            .is_some());
+        assert!(tracer
+            .end_segment(
+                0,
+                inner,
+                ms(30),
+                "Custom/inner".to_owned(),
+                NodeAttrs::default()
+            )
+            .is_none());
+        // A segment cannot be ended twice.
+        assert!(tracer
+            .end_segment(
+                0,
+                outer,
+                ms(40),
+                "Custom/outer".to_owned(),
+                NodeAttrs::default()
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_threads() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut tracer = Tracer::new(start, Duration::from_millis(2), false);
+        let thread = tracer.new_thread();
+        assert_eq!(thread, 1);
+
+        let main = tracer.start_segment(0, ms(0));
+        let worker = tracer.start_segment(thread, ms(10));
+        // Segments in different threads don't nest.
+        tracer
+            .end_segment(
+                0,
+                main,
+                ms(50),
+                "Custom/main".to_owned(),
+                NodeAttrs::default(),
+            )
+            .unwrap();
+        let worker_end = tracer
+            .end_segment(
+                thread,
+                worker,
+                ms(70),
+                "Custom/worker".to_owned(),
+                NodeAttrs::default(),
+            )
+            .unwrap();
+        assert_eq!(worker_end.exclusive, Duration::from_millis(60));
+        assert_eq!(
+            tracer.root_exclusive(0, Duration::from_millis(100)),
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            tracer.total_time(Duration::from_millis(100)),
+            Duration::from_millis(160)
+        );
+
+        let nodes = tracer.take_nodes();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].name, "Custom/main");
+        assert_eq!(nodes[1].name, "Custom/worker");
+        assert!(nodes[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_segment_threshold() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut tracer = Tracer::new(start, Duration::from_millis(5), false);
+
+        let outer = tracer.start_segment(0, ms(0));
+        let inner = tracer.start_segment(0, ms(0));
+        tracer.end_segment(
+            0,
+            inner,
+            ms(10),
+            "Custom/inner".to_owned(),
+            NodeAttrs::default(),
+        );
+        tracer.end_segment(
+            0,
+            outer,
+            ms(11),
+            "Custom/outer".to_owned(),
+            NodeAttrs::default(),
+        );
+        let short = tracer.start_segment(0, ms(20));
+        tracer.end_segment(
+            0,
+            short,
+            ms(21),
+            "Custom/short".to_owned(),
+            NodeAttrs::default(),
+        );
+
+        let nodes = tracer.take_nodes();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].name, "Custom/outer");
+        assert_eq!(nodes[0].children[0].name, "Custom/inner");
+    }
+
+    #[test]
+    fn test_spans() {
+        let start = Instant::now();
+        let ms = |n: u64| start + Duration::from_millis(n);
+        let mut tracer = Tracer::new(start, Duration::from_millis(2), true);
+        let root_span_id = tracer.root_span_id().unwrap().to_owned();
+
+        let outer = tracer.start_segment(0, ms(0));
+        let inner = tracer.start_segment(0, ms(10));
+        let inner_span_id = tracer.current_span_id(0).unwrap().to_owned();
+        assert_ne!(inner_span_id, root_span_id);
+        tracer.end_segment(
+            0,
+            inner,
+            ms(20),
+            "Custom/inner".to_owned(),
+            NodeAttrs::default(),
+        );
+        tracer.end_segment(
+            0,
+            outer,
+            ms(30),
+            "Custom/outer".to_owned(),
+            NodeAttrs::default(),
+        );
+        assert_eq!(tracer.current_span_id(0), Some(&*root_span_id));
+
+        let spans = tracer.take_spans();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].name, "Custom/inner");
+        assert_eq!(spans[0].guid, inner_span_id);
+        assert_eq!(spans[0].duration, Duration::from_millis(10));
+        assert_eq!(spans[1].name, "Custom/outer");
+        assert_eq!(spans[1].parent_id, root_span_id);
+        assert_eq!(spans[0].parent_id, spans[1].guid);
+    }
+}
@@ -2,18 +2,50 @@
 // Copyright 2020 Masaki Hara.
 
 use parking_lot::Mutex;
-use std::sync::Arc;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::apdex::ApdexZone;
+use crate::app_run::AppRun;
+use crate::attributes::{add_user_attribute, AttributeError, AttributeValue, Destinations};
+use crate::config::Config;
+use crate::cross_process::{
+    self, accept_app_data, insert_app_data_header, primary_app_name, AppData, TxnCrossProcess,
+};
+use crate::distributed_tracing::{
+    accept_newrelic, accept_w3c, insert_newrelic, insert_w3c, new_guid, new_trace_id,
+    InboundPayload, OutboundPayload, TRACEPARENT_HEADER, TRANSPORT_HTTP, UNKNOWN_CALLER,
+};
+use crate::errors::ErrorData;
+use crate::metric_names::{
+    duration_by_caller_metric, errors_by_caller_metric, transport_duration_metric, RollupNames,
+};
+use crate::metrics::MetricTable;
 use crate::payloads::analytics_events::{
-    AnalyticsEvent, AnalyticsEventWithAttrs, TransactionEvent, TransactionShared,
+    AnalyticsEvent, AnalyticsEventWithAttrs, CrossProcessIntrinsics, DistributedTraceIntrinsics,
+    ErrorEvent, TransactionEvent, TransactionShared,
 };
+use crate::payloads::errors::{ErrorParams, TracedError};
+use crate::payloads::span_events::{SpanCategory, SpanEvent, SpanIntrinsics};
+use crate::payloads::transaction_trace::Intrinsics;
 use crate::payloads::{AgentAttrs, UserAttrs};
+use crate::priority::Priority;
+use crate::segments::{
+    safe_url, DatastoreSegment, ExternalSegment, MessageProducerSegment, SegmentGuard, SegmentKind,
+};
+use crate::tracing::{SegmentStartTime, SpanAttrs, Tracer};
 use crate::{AppState, ApplicationInner};
 
 const MAIN_THREAD_ID: usize = 0;
 
+thread_local! {
+    /// The transactions started or entered in this thread, from the outermost one, paired with
+    /// their thread ids.
+    static CURRENT_TRANSACTIONS: RefCell<Vec<(Weak<TransactionInner>, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
 #[derive(Debug)]
 pub struct TransactionGuard {
     txn: Transaction,
@@ -30,6 +62,35 @@ impl std::ops::Deref for TransactionGuard {
 impl std::ops::Drop for TransactionGuard {
     fn drop(&mut self) {
         self.txn.inner.stop();
+        let inner = Arc::downgrade(&self.txn.inner);
+        CURRENT_TRANSACTIONS.with(|current| {
+            current
+                .borrow_mut()
+                .retain(|(txn, _)| !txn.ptr_eq(&inner) && txn.strong_count() > 0);
+        });
+    }
+}
+
+/// Makes a transaction current in this thread until dropped.
+#[cfg(feature = "futures")]
+#[derive(Debug)]
+pub(crate) struct CurrentGuard {
+    inner: Weak<TransactionInner>,
+    thread_id: usize,
+}
+
+#[cfg(feature = "futures")]
+impl std::ops::Drop for CurrentGuard {
+    fn drop(&mut self) {
+        CURRENT_TRANSACTIONS.with(|current| {
+            let mut current = current.borrow_mut();
+            let pos = current.iter().rposition(|(txn, thread_id)| {
+                txn.ptr_eq(&self.inner) && *thread_id == self.thread_id
+            });
+            if let Some(pos) = pos {
+                current.remove(pos);
+            }
+        });
     }
 }
 
@@ -45,111 +106,605 @@ impl Transaction {
         app: &Arc<ApplicationInner>,
         name: &str,
         web_request: Option<WebRequest>,
+        is_message: bool,
     ) -> TransactionGuard {
         let now = Instant::now();
+        let mut txn_state = TransactionState::new(&app.config, now, name);
+        if let Some(web_request) = web_request {
+            let cat_run = cross_process_run(app);
+            txn_state.set_web_request(web_request, SystemTime::now(), cat_run.as_deref());
+        }
+        let inner = Arc::new(TransactionInner {
+            app: app.clone(),
+            start: now,
+            is_message,
+            state: Mutex::new(Some(txn_state)),
+        });
+        CURRENT_TRANSACTIONS.with(|current| {
+            let mut current = current.borrow_mut();
+            // Guards dropped in other threads leave dead entries.
+            current.retain(|(txn, _)| txn.strong_count() > 0);
+            current.push((Arc::downgrade(&inner), MAIN_THREAD_ID));
+        });
         TransactionGuard {
             txn: Transaction {
-                inner: Arc::new(TransactionInner {
-                    app: app.clone(),
-                    start: now,
-                    name: name.to_owned(),
-                    web_request,
-                    state: Mutex::new(Some(TransactionState::new(now))),
-                }),
+                inner,
                 thread_id: MAIN_THREAD_ID,
             },
         }
     }
+
+    /// Returns the innermost running transaction started in this thread or entered by the
+    /// future being polled.
+    pub fn current() -> Option<Transaction> {
+        Self::find_current(|inner| inner.state.lock().is_some())
+    }
+
+    /// Returns the innermost running transaction of the application started in this thread.
+    ///
+    /// It never blocks, skipping the transactions whose state is locked.
+    pub(crate) fn current_in(app: &Arc<ApplicationInner>) -> Option<Transaction> {
+        Self::find_current(|inner| {
+            Arc::ptr_eq(&inner.app, app)
+                && inner.state.try_lock().is_some_and(|state| state.is_some())
+        })
+    }
+
+    fn find_current<F>(mut pred: F) -> Option<Transaction>
+    where
+        F: FnMut(&TransactionInner) -> bool,
+    {
+        CURRENT_TRANSACTIONS.with(|current| {
+            current
+                .borrow()
+                .iter()
+                .rev()
+                .filter_map(|(txn, thread_id)| Some((txn.upgrade()?, *thread_id)))
+                .find(|(inner, _)| pred(inner))
+                .map(|(inner, thread_id)| Transaction { inner, thread_id })
+        })
+    }
+
+    /// Makes the transaction current in this thread until the guard is dropped.
+    #[cfg(feature = "futures")]
+    pub(crate) fn enter(&self) -> CurrentGuard {
+        let inner = Arc::downgrade(&self.inner);
+        CURRENT_TRANSACTIONS.with(|current| {
+            current.borrow_mut().push((inner.clone(), self.thread_id));
+        });
+        CurrentGuard {
+            inner,
+            thread_id: self.thread_id,
+        }
+    }
+
+    /// Returns a handle of the transaction to use in another thread.
+    ///
+    /// The handle has its own segment stack, so its segments are not nested in the segments of
+    /// the other threads. They are shown as separate branches in the transaction trace.
+    pub fn new_thread(&self) -> Transaction {
+        let mut state = self.inner.state.lock();
+        let thread_id = if let Some(state) = state.as_mut() {
+            state.tracer.new_thread()
+        } else {
+            self.thread_id
+        };
+        Transaction {
+            inner: self.inner.clone(),
+            thread_id,
+        }
+    }
+
+    /// Starts a segment timing a part of the transaction.
+    ///
+    /// The segment is recorded as a `Custom/<name>` metric when the returned guard is dropped.
+    /// Segments started while another segment of the same thread is running are nested in it.
+    pub fn start_segment(&self, name: &str) -> SegmentGuard {
+        SegmentGuard::new(self, SegmentKind::Custom(name.to_owned()))
+    }
+
+    /// Starts a segment timing a datastore call.
+    ///
+    /// The time is recorded in the `Datastore/` metrics and the database call count and duration
+    /// of the transaction.
+    pub fn start_datastore_segment(&self, segment: DatastoreSegment) -> SegmentGuard {
+        SegmentGuard::new(self, SegmentKind::Datastore(segment))
+    }
+
+    /// Starts a segment timing an outgoing HTTP call.
+    ///
+    /// The time is recorded in the `External/` metrics and the external call count and duration
+    /// of the transaction.
+    pub fn start_external_segment<T: Into<ExternalSegment>>(&self, segment: T) -> SegmentGuard {
+        SegmentGuard::new(self, SegmentKind::External(segment.into()))
+    }
+
+    /// Starts a segment timing the publication of a message.
+    ///
+    /// The time is recorded in the `MessageBroker/<library>/<type>/Produce/...` metric.
+    pub fn start_message_producer_segment(&self, segment: MessageProducerSegment) -> SegmentGuard {
+        SegmentGuard::new(self, SegmentKind::MessageProducer(segment))
+    }
+
+    /// Records an error in the transaction.
+    ///
+    /// The error class is the type name of the error. For trait objects, it is taken from the
+    /// `Debug` representation. The messages of the error sources are appended to the message.
+    ///
+    /// The stack trace is captured if enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+    pub fn notice_error<E: std::error::Error + ?Sized>(&self, error: &E) {
+        self.notice_error_internal(ErrorData::from_error(error, &Backtrace::capture()));
+    }
+
+    /// Records an error in the transaction with the backtrace captured where the error occurred.
+    pub fn notice_error_with_backtrace<E: std::error::Error + ?Sized>(
+        &self,
+        error: &E,
+        backtrace: &Backtrace,
+    ) {
+        self.notice_error_internal(ErrorData::from_error(error, backtrace));
+    }
+
+    /// Records an error in the transaction with the explicit class and message.
+    pub fn notice_error_with_class(&self, class: &str, message: &str) {
+        self.notice_error_internal(ErrorData::new(class, message, &Backtrace::capture()));
+    }
+
+    /// Changes the name of the transaction.
+    ///
+    /// Ignored once the name is sent to other applications by cross application tracing.
+    pub fn set_name(&self, name: &str) {
+        let mut state = self.inner.state.lock();
+        if let Some(state) = state.as_mut() {
+            if state.name_frozen {
+                return;
+            }
+            state.name = name.to_owned();
+        }
+    }
+
+    /// Discards the transaction. Nothing is recorded when it ends.
+    pub fn ignore(&self) {
+        let mut state = self.inner.state.lock();
+        if let Some(state) = state.as_mut() {
+            state.ignored = true;
+        }
+    }
+
+    /// Marks the transaction as a web transaction processing the request.
+    pub fn set_web_request<T: Into<WebRequest>>(&self, request: T) {
+        let request = request.into();
+        let elapsed = self.inner.start.elapsed();
+        let cat_run = cross_process_run(&self.inner.app);
+        let mut state = self.inner.state.lock();
+        if let Some(state) = state.as_mut() {
+            state.set_web_request(request, SystemTime::now() - elapsed, cat_run.as_deref());
+        }
+    }
+
+    /// Adds the trace context headers to an outgoing request if distributed tracing is enabled,
+    /// or the cross application tracing headers otherwise.
+    pub fn insert_distributed_trace_headers(&self, headers: &mut http::HeaderMap) {
+        let config = &self.inner.app.config;
+        if !config.distributed_tracer.enabled && !cross_process::enabled(config) {
+            return;
+        }
+        let run = if let Some(run) = self.inner.app.current_run() {
+            run
+        } else {
+            return;
+        };
+        let mut state = self.inner.state.lock();
+        let state = if let Some(state) = state.as_mut() {
+            state
+        } else {
+            return;
+        };
+        if !config.distributed_tracer.enabled {
+            let is_web = state.web_request.is_some();
+            let name = if let Some(name) = self.inner.final_name(&state.name, is_web, &run) {
+                name
+            } else {
+                return;
+            };
+            state.name_frozen = true;
+            let path_hash = state
+                .cross_process
+                .path_hash(&name, primary_app_name(&config.app_name));
+            state
+                .cross_process
+                .insert_request_headers(headers, &run, &state.guid, path_hash);
+            return;
+        }
+        let sampled = state.sampled(Some(&run));
+        let other_entries = state
+            .inbound
+            .as_ref()
+            .map_or(&[][..], |inbound| &inbound.other_entries);
+        // Span events are only sent for sampled transactions.
+        let span_id = if sampled {
+            state.tracer.current_span_id(self.thread_id)
+        } else {
+            None
+        };
+        let payload = OutboundPayload {
+            trace_id: &state.trace_id,
+            span_id: span_id.unwrap_or(&state.guid),
+            transaction_id: &state.guid,
+            sampled,
+            priority: state.priority,
+            account: &run.account_id,
+            app: &run.primary_application_id,
+            trusted_account_key: &run.trusted_account_key,
+            other_entries,
+            timestamp: SystemTime::now(),
+        };
+        insert_w3c(headers, &payload);
+        if !self
+            .inner
+            .app
+            .config
+            .distributed_tracer
+            .exclude_newrelic_header
+        {
+            insert_newrelic(headers, &payload);
+        }
+        state.distributed_trace_sent = true;
+    }
+
+    /// Reads the trace context headers of the incoming request if distributed tracing is enabled.
+    ///
+    /// It should be called before any outgoing request is made, and only once.
+    pub fn accept_distributed_trace_headers(&self, headers: &http::HeaderMap) {
+        if !self.inner.app.config.distributed_tracer.enabled {
+            return;
+        }
+        let run = if let Some(run) = self.inner.app.current_run() {
+            run
+        } else {
+            return;
+        };
+        let mut state = self.inner.state.lock();
+        let state = if let Some(state) = state.as_mut() {
+            state
+        } else {
+            return;
+        };
+        if state.inbound.is_some() || state.distributed_trace_sent {
+            log::debug!("trace context is accepted too late");
+            return;
+        }
+        // The W3C headers take precedence over the `newrelic` header.
+        let inbound = if headers.contains_key(TRACEPARENT_HEADER) {
+            accept_w3c(headers, &run.trusted_account_key, TRANSPORT_HTTP)
+        } else {
+            accept_newrelic(headers, &run.trusted_account_key, TRANSPORT_HTTP)
+        };
+        let inbound = if let Some(inbound) = inbound {
+            inbound
+        } else {
+            return;
+        };
+        state.trace_id = inbound.trace_id.clone();
+        if let Some(nr) = &inbound.nr {
+            if let Some(priority) = nr.priority {
+                state.priority = priority;
+            }
+            state.sampled = nr.sampled;
+            if nr.sampled == Some(false) {
+                state.tracer.stop_collecting_spans();
+            }
+        }
+        state.inbound = Some(inbound);
+    }
+
+    /// Adds the `X-NewRelic-App-Data` header to the response if the request came from a trusted
+    /// application with the cross application tracing headers.
+    ///
+    /// The transaction name used in the header is fixed at this point, and later calls to
+    /// [`Transaction::set_name`] are ignored.
+    pub fn insert_response_headers(&self, headers: &mut http::HeaderMap) {
+        if !cross_process::enabled(&self.inner.app.config) {
+            return;
+        }
+        let run = if let Some(run) = self.inner.app.current_run() {
+            run
+        } else {
+            return;
+        };
+        let mut state = self.inner.state.lock();
+        let state = if let Some(state) = state.as_mut() {
+            state
+        } else {
+            return;
+        };
+        if state.cross_process.client_id.is_none() {
+            return;
+        }
+        state.name_frozen = true;
+        let is_web = state.web_request.is_some();
+        let name = if let Some(name) = self.inner.final_name(&state.name, is_web, &run) {
+            name
+        } else {
+            return;
+        };
+        insert_app_data_header(
+            headers,
+            &run,
+            &name,
+            state.queue_duration.unwrap_or_default(),
+            self.inner.start.elapsed(),
+            &state.guid,
+        );
+    }
+
+    /// Adds a user attribute to the transaction.
+    ///
+    /// It is sent with the transaction event, the transaction trace and the errors.
+    /// Up to 64 attributes are allowed; adding an existing key replaces its value.
+    pub fn add_attribute<V: Into<AttributeValue>>(
+        &self,
+        key: &str,
+        value: V,
+    ) -> Result<(), AttributeError> {
+        let mut state = self.inner.state.lock();
+        if let Some(state) = state.as_mut() {
+            add_user_attribute(&mut state.user_attrs, key, value.into())?;
+        }
+        Ok(())
+    }
+
+    /// Records the response of a web transaction.
+    ///
+    /// A status code of 400 or above is recorded as an error, unless ignored by
+    /// [`ErrorCollectorConfig::ignore_status_codes`](crate::config::ErrorCollectorConfig::ignore_status_codes).
+    /// Only the first call takes effect, like writing the header of an HTTP response.
+    pub fn set_web_response(&self, response: &http::response::Parts) {
+        {
+            let mut state = self.inner.state.lock();
+            if let Some(state) = state.as_mut() {
+                if state.web_response.is_some() {
+                    return;
+                }
+                state.web_response = Some(WebResponse {
+                    status: response.status,
+                    headers: response.headers.clone(),
+                });
+            } else {
+                return;
+            }
+        }
+        let status = response.status;
+        if status.is_client_error() || status.is_server_error() {
+            self.notice_error_internal(ErrorData::new(
+                status.as_str(),
+                status.canonical_reason().unwrap_or(""),
+                &Backtrace::disabled(),
+            ));
+        }
+    }
+
+    pub(crate) fn notice_error_internal(&self, error: ErrorData) {
+        let error = if let Some(error) = self.inner.app.filter_error(error) {
+            error
+        } else {
+            return;
+        };
+        let mut state = self.inner.state.lock();
+        if let Some(state) = state.as_mut() {
+            if state.errors.len() < crate::limits::MAX_TXN_ERRORS {
+                state.errors.push(error);
+            }
+        }
+    }
+
+    /// Records an already filtered error without blocking, as in the panic hook.
+    pub(crate) fn try_notice_error(&self, error: ErrorData) {
+        if let Some(mut state) = self.inner.state.try_lock() {
+            if let Some(state) = state.as_mut() {
+                if state.errors.len() < crate::limits::MAX_TXN_ERRORS {
+                    state.errors.push(error);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn start_segment_internal(&self, now: Instant) -> Option<SegmentStartTime> {
+        let mut state = self.inner.state.lock();
+        let state = state.as_mut()?;
+        Some(state.tracer.start_segment(self.thread_id, now))
+    }
+
+    pub(crate) fn end_segment_internal(
+        &self,
+        start: SegmentStartTime,
+        now: Instant,
+        kind: &SegmentKind,
+    ) {
+        // Read before locking the state, as it locks the application.
+        let app_data = match kind {
+            SegmentKind::External(segment) => self.accept_app_data(segment),
+            _ => None,
+        };
+        let mut state = self.inner.state.lock();
+        let state = if let Some(state) = state.as_mut() {
+            state
+        } else {
+            return;
+        };
+        let tracer = &mut state.tracer;
+        match kind {
+            SegmentKind::Custom(name) => {
+                tracer.end_basic_segment(self.thread_id, start, now, name);
+            }
+            SegmentKind::Datastore(segment) => {
+                tracer.end_datastore_segment(self.thread_id, start, now, segment);
+            }
+            SegmentKind::External(segment) => {
+                tracer.end_external_segment(self.thread_id, start, now, segment, app_data);
+            }
+            SegmentKind::MessageProducer(segment) => {
+                tracer.end_message_segment(self.thread_id, start, now, segment);
+            }
+        }
+    }
+
+    fn accept_app_data(&self, segment: &ExternalSegment) -> Option<AppData> {
+        let app_data = segment.app_data.as_deref()?;
+        if !cross_process::enabled(&self.inner.app.config) {
+            return None;
+        }
+        accept_app_data(app_data, &*self.inner.app.current_run()?)
+    }
 }
 
 #[derive(Debug)]
 struct TransactionInner {
     app: Arc<ApplicationInner>,
     start: Instant,
-    name: String,
-    web_request: Option<WebRequest>,
+    is_message: bool,
     state: Mutex<Option<TransactionState>>,
 }
 
 impl TransactionInner {
-    fn final_name(&self) -> String {
-        // TODO: apply URL rules
-        let name = if self.name.starts_with('/') {
-            &self.name[1..]
-        } else {
-            &self.name
-        };
-        let prefix = if self.web_request.is_some() {
+    /// Applies the naming rules. Returns `None` if the transaction should be ignored.
+    fn final_name(&self, name: &str, is_web: bool, run: &AppRun) -> Option<String> {
+        let name = run.url_rules.apply(name)?;
+        let name = name.strip_prefix('/').unwrap_or(&name);
+        let prefix = if is_web {
             crate::metric_names::WEB_METRIC_PREFIX
+        } else if self.is_message {
+            crate::metric_names::MESSAGE_METRIC_PREFIX
         } else {
             crate::metric_names::BACKGROUND_METRIC_PREFIX
         };
-        // TODO: apply transaction name rules
-        // TODO: apply segment terms
-        format!("{}/{}", prefix, name)
+        let name = run.txn_name_rules.apply(&format!("{}/{}", prefix, name))?;
+        Some(run.segment_terms.apply(&name))
     }
 
     fn stop(&self) {
-        let is_web = self.web_request.is_some();
+        let mut txn_state = if let Some(txn_state) = self.state.lock().take() {
+            txn_state
+        } else {
+            return;
+        };
+        if txn_state.ignored {
+            return;
+        }
+        let distributed_tracing = self.app.config.distributed_tracer.enabled;
+        // Decide before reading the priority, which may be raised.
+        let sampled = if distributed_tracing {
+            Some(txn_state.sampled(self.app.current_run().as_deref()))
+        } else {
+            None
+        };
+        let inbound = txn_state.inbound;
+        let guid = txn_state.guid;
+        let trace_id = txn_state.trace_id;
+        let is_web = txn_state.web_request.is_some();
+        let txn_name = txn_state.name;
+        let web_request = txn_state.web_request;
+        let queue_duration = txn_state.queue_duration;
+        let mut tracer = txn_state.tracer;
+        let errors = txn_state.errors;
+        let user_attrs = txn_state.user_attrs;
+        let web_response = txn_state.web_response;
+        let has_errors = errors.iter().any(|error| !error.expected);
+        let has_expected_errors = errors.iter().any(|error| error.expected);
+        let priority = txn_state.priority;
+        let cross_process = txn_state.cross_process;
         let mut state = self.app.state.lock();
         if let AppState::Running { run, harvest } = &mut *state {
             // Ensure immutability
             let run = &**run;
 
-            let name = self.final_name();
+            let name = if let Some(name) = self.final_name(&txn_name, is_web, run) {
+                name
+            } else {
+                return;
+            };
             let duration = Instant::now()
                 .checked_duration_since(self.start)
                 .unwrap_or_else(|| Duration::from_secs(0));
             let end = SystemTime::now();
             let start = end - duration;
+            let exclusive = tracer.root_exclusive(MAIN_THREAD_ID, duration);
+            // Includes the time spent in the other threads.
+            let total_time = tracer.total_time(duration);
             let start_from_unix = start.duration_since(UNIX_EPOCH).unwrap_or_default();
+            let path_hash = if cross_process::enabled(&self.app.config) && cross_process.used() {
+                let app_name = primary_app_name(&self.app.config.app_name);
+                Some(cross_process.path_hash(&name, app_name))
+            } else {
+                None
+            };
+            let trip_id = path_hash
+                .as_ref()
+                .map(|_| cross_process.trip_id(&guid).to_owned());
             let mut agent_attrs = AgentAttrs::default();
-            if let Some(web_request) = &self.web_request {
-                agent_attrs.0.insert(
-                    "request.method".to_owned(),
-                    web_request.method.to_string().into(),
-                );
-                agent_attrs
-                    .0
-                    .insert("request.uri".to_owned(), web_request.uri.to_string().into());
-                if let Some(host) = web_request.headers.get("Host") {
-                    agent_attrs.0.insert(
-                        "request.headers.host".to_owned(),
-                        String::from_utf8_lossy(host.as_bytes()).into_owned().into(),
-                    );
-                }
+            if let Some(web_request) = &web_request {
+                add_request_attrs(&mut agent_attrs, web_request);
+            }
+            if let Some(web_response) = &web_response {
+                add_response_attrs(&mut agent_attrs, web_response);
             }
+            let shared = TransactionShared {
+                duration: duration.as_secs_f64(),
+                queue_duration: queue_duration.map(|d| d.as_secs_f64()),
+                external_call_count: tracer.external_call_count(),
+                external_duration: tracer.external_duration(),
+                database_call_count: tracer.datastore_call_count(),
+                database_duration: tracer.datastore_duration(),
+                synthetics_resource_id: None,
+                synthetics_job_id: None,
+                synthetics_monitor_id: None,
+            };
+            let filter = &self.app.attribute_filter;
+            let apdex_zone = if !is_web {
+                None
+            } else if has_errors {
+                Some(ApdexZone::Failing)
+            } else {
+                Some(ApdexZone::calculate(run.apdex_threshold(&name), duration))
+            };
             let attrs = AnalyticsEventWithAttrs {
                 event: AnalyticsEvent::Transaction(TransactionEvent {
                     name: name.clone(),
                     timestamp: start_from_unix.as_secs() as i64,
-                    apdex_perf_zone: if is_web {
-                        // TODO: Apdex T may depend on transaction name
-                        Some(ApdexZone::calculate(duration, run.apdex_t))
-                    } else {
-                        None
-                    },
-                    error: false,
-                    shared: TransactionShared {
-                        duration: duration.as_secs_f64(),
-                        queue_duration: None,
-                        external_call_count: None,
-                        external_duration: None,
-                        database_call_count: None,
-                        database_duration: None,
-                        synthetics_resource_id: None,
-                        synthetics_job_id: None,
-                        synthetics_monitor_id: None,
-                    },
-                    total_time: duration.as_secs_f64(),
+                    apdex_perf_zone: apdex_zone,
+                    error: has_errors,
+                    shared: shared.clone(),
+                    total_time: total_time.as_secs_f64(),
+                    distributed_trace: sampled.map(|sampled| {
+                        distributed_trace_intrinsics(
+                            &guid,
+                            &trace_id,
+                            priority,
+                            sampled,
+                            inbound.as_ref(),
+                            start,
+                        )
+                    }),
+                    cross_process: path_hash.as_ref().map(|path_hash| {
+                        Box::new(CrossProcessIntrinsics {
+                            guid: guid.clone(),
+                            trip_id: cross_process.trip_id(&guid).to_owned(),
+                            path_hash: path_hash.clone(),
+                            referring_path_hash: cross_process.referring_path_hash.clone(),
+                            referring_txn_guid: cross_process.referring_txn_guid.clone(),
+                            alternate_path_hashes: cross_process.alternate_path_hashes(path_hash),
+                        })
+                    }),
                 }),
-                user_attrs: UserAttrs::default(),
-                agent_attrs: agent_attrs.clone(),
+                user_attrs: filter.user_attrs(&user_attrs, Destinations::TXN_EVENT),
+                agent_attrs: filter.agent_attrs(&agent_attrs, Destinations::TXN_EVENT),
             };
-            harvest.txn_events.push(attrs);
+            if self.app.config.transaction_events.enabled {
+                harvest.txn_events.push(attrs, priority);
+            }
             harvest
                 .metric_table
-                .add_duration(&name, None, duration, Duration::from_secs(0), true);
+                .add_duration(&name, None, duration, exclusive, true);
             let rollup_name = crate::metric_names::rollup_name(is_web);
             harvest.metric_table.add_duration(
                 rollup_name,
@@ -167,14 +722,222 @@ impl TransactionInner {
                     true,
                 );
             }
+            if let Some(queue_duration) = queue_duration {
+                harvest.metric_table.add_duration(
+                    crate::metric_names::QUEUE_METRIC,
+                    None,
+                    queue_duration,
+                    queue_duration,
+                    true,
+                );
+            }
+            if let Some(apdex_zone) = apdex_zone {
+                let threshold = run.apdex_threshold(&name);
+                harvest.metric_table.add_apdex(
+                    crate::metric_names::APDEX_ROLLUP,
+                    None,
+                    threshold,
+                    apdex_zone,
+                    true,
+                );
+                harvest.metric_table.add_apdex(
+                    &crate::metric_names::apdex_name(&name),
+                    None,
+                    threshold,
+                    apdex_zone,
+                    false,
+                );
+            }
             let total_name = crate::metric_names::total_time_name(&name, is_web);
             let total_rollup_name = crate::metric_names::total_time_rollup_name(is_web);
             harvest
                 .metric_table
-                .add_duration(&total_name, None, duration, duration, false);
-            harvest
-                .metric_table
-                .add_duration(total_rollup_name, None, duration, duration, true);
+                .add_duration(&total_name, None, total_time, total_time, false);
+            harvest.metric_table.add_duration(
+                total_rollup_name,
+                None,
+                total_time,
+                total_time,
+                true,
+            );
+            tracer.merge_breakdown_metrics(&name, is_web, &mut harvest.metric_table);
+            if distributed_tracing {
+                add_caller_metrics(
+                    &mut harvest.metric_table,
+                    inbound.as_ref(),
+                    start,
+                    duration,
+                    is_web,
+                    has_errors,
+                );
+            }
+            if let (Some(client_id), Some(_)) = (&cross_process.client_id, &path_hash) {
+                harvest.metric_table.add_duration(
+                    &crate::metric_names::client_application_metric(client_id),
+                    None,
+                    duration,
+                    duration,
+                    false,
+                );
+            }
+            if sampled == Some(true) && run.collect_span_events {
+                if let Some(root_span_id) = tracer.root_span_id().map(str::to_owned) {
+                    let new_intrinsics =
+                        |span_guid: String,
+                         parent_id: Option<String>,
+                         name: String,
+                         timestamp: SystemTime,
+                         duration: Duration,
+                         attrs: &SpanAttrs| {
+                            SpanIntrinsics {
+                                trace_id: trace_id.clone(),
+                                guid: span_guid,
+                                parent_id,
+                                transaction_id: guid.clone(),
+                                sampled: true,
+                                priority,
+                                timestamp: timestamp
+                                    .duration_since(UNIX_EPOCH)
+                                    .unwrap_or_default()
+                                    .as_millis() as i64,
+                                duration: duration.as_secs_f64(),
+                                name,
+                                category: attrs.category,
+                                entry_point: false,
+                                kind: if attrs.category == SpanCategory::Generic {
+                                    None
+                                } else {
+                                    Some("client".to_owned())
+                                },
+                                component: attrs.component.clone(),
+                                trusted_parent_id: None,
+                                tracing_vendors: None,
+                            }
+                        };
+                    let root_intrinsics = SpanIntrinsics {
+                        entry_point: true,
+                        trusted_parent_id: inbound
+                            .as_ref()
+                            .and_then(|inbound| inbound.nr.as_ref())
+                            .and_then(|nr| nr.span_id.clone()),
+                        tracing_vendors: inbound
+                            .as_ref()
+                            .filter(|inbound| !inbound.other_entries.is_empty())
+                            .map(|inbound| {
+                                inbound
+                                    .other_entries
+                                    .iter()
+                                    .map(|entry| entry.split('=').next().unwrap_or_default())
+                                    .collect::<Vec<_>>()
+                                    .join(",")
+                            }),
+                        ..new_intrinsics(
+                            root_span_id,
+                            inbound
+                                .as_ref()
+                                .and_then(|inbound| inbound.parent_span_id.clone()),
+                            name.clone(),
+                            start,
+                            duration,
+                            &SpanAttrs::default(),
+                        )
+                    };
+                    harvest.span_events.push(
+                        SpanEvent {
+                            intrinsics: root_intrinsics,
+                            user_attrs: filter.user_attrs(&user_attrs, Destinations::SPAN),
+                            agent_attrs: filter.agent_attrs(&agent_attrs, Destinations::SPAN),
+                        },
+                        priority,
+                    );
+                    for span in tracer.take_spans() {
+                        let timestamp = start + span.start.saturating_duration_since(self.start);
+                        harvest.span_events.push(
+                            SpanEvent {
+                                intrinsics: new_intrinsics(
+                                    span.guid,
+                                    Some(span.parent_id),
+                                    span.name,
+                                    timestamp,
+                                    span.duration,
+                                    &span.attrs,
+                                ),
+                                user_attrs: UserAttrs::default(),
+                                agent_attrs: filter
+                                    .agent_attrs(&span.attrs.agent_attrs, Destinations::SPAN),
+                            },
+                            priority,
+                        );
+                    }
+                }
+            }
+
+            if has_expected_errors {
+                harvest.metric_table.add_count(
+                    crate::metric_names::ERRORS_EXPECTED_ALL,
+                    None,
+                    1.0,
+                    true,
+                );
+            }
+            if has_errors {
+                harvest
+                    .metric_table
+                    .add_count(crate::metric_names::ERRORS_ALL, None, 1.0, true);
+                harvest.metric_table.add_count(
+                    crate::metric_names::errors_rollup_name(is_web),
+                    None,
+                    1.0,
+                    true,
+                );
+                harvest.metric_table.add_count(
+                    &crate::metric_names::errors_name(&name),
+                    None,
+                    1.0,
+                    true,
+                );
+            }
+            let error_user_attrs = filter.user_attrs(&user_attrs, Destinations::ERROR);
+            let error_agent_attrs = filter.agent_attrs(&agent_attrs, Destinations::ERROR);
+            for error in &errors {
+                let timestamp = error.when.duration_since(UNIX_EPOCH).unwrap_or_default();
+                if run.collect_error_events && self.app.config.error_collector.capture_events {
+                    let event = AnalyticsEventWithAttrs {
+                        event: AnalyticsEvent::TransactionError(ErrorEvent {
+                            class: error.class.clone(),
+                            message: error.message.clone(),
+                            expected: error.expected,
+                            timestamp: timestamp.as_secs_f64(),
+                            transaction_name: name.clone(),
+                            shared: Some(shared.clone()),
+                        }),
+                        user_attrs: error_user_attrs.clone(),
+                        agent_attrs: error_agent_attrs.clone(),
+                    };
+                    harvest.error_events.push(event, priority);
+                }
+                if run.collect_errors {
+                    harvest.error_traces.push(TracedError {
+                        timestamp: timestamp.as_secs_f64() * 1000.0,
+                        name: name.clone(),
+                        message: error.message.clone(),
+                        class: error.class.clone(),
+                        params: ErrorParams {
+                            agent_attributes: error_agent_attrs.clone(),
+                            user_attributes: error_user_attrs.clone(),
+                            intrinsics: Intrinsics {
+                                total_time: total_time.as_secs_f64(),
+                                trip_id: trip_id.clone(),
+                                path_hash: path_hash.clone(),
+                            },
+                            stack_trace: error.stack_trace.clone(),
+                            request_uri: web_request
+                                .as_ref()
+                                .map(|web_request| safe_url(&web_request.uri)),
+                        },
+                    });
+                }
+            }
 
             // TODO: check is_synthetics
             // TODO: duration and is_apdex_failing configs
@@ -182,18 +945,16 @@ impl TransactionInner {
                 && duration >= Duration::from_millis(500);
             if should_save_trace {
                 use crate::payloads::transaction_trace::{
-                    DummyStruct, Intrinsics, Node, NodeAttrs, Properties, TraceData,
-                    TransactionTrace,
+                    DummyStruct, Node, NodeAttrs, Properties, TraceData, TransactionTrace,
                 };
 
                 let trace = TransactionTrace {
                     start: start_from_unix.as_micros() as i64,
                     duration: duration.as_secs_f64() * 1000.0,
                     name: name.clone(),
-                    request_uri: self
-                        .web_request
+                    request_uri: web_request
                         .as_ref()
-                        .map(|web_request| web_request.uri.to_string()),
+                        .map(|web_request| safe_url(&web_request.uri)),
                     trace_data: TraceData {
                         unused1: 0.0,
                         unused2: DummyStruct {},
@@ -202,30 +963,37 @@ impl TransactionInner {
                             relative_start_millis: 0,
                             relative_stop_millis: duration.as_millis() as i64,
                             name: "ROOT".to_owned(),
-                            attrs: NodeAttrs {
-                                exclusive_duration_millis: None,
-                            },
+                            attrs: NodeAttrs::default(),
                             children: vec![Node {
                                 relative_start_millis: 0,
                                 relative_stop_millis: duration.as_millis() as i64,
                                 name,
                                 attrs: NodeAttrs {
                                     exclusive_duration_millis: Some(
-                                        duration.as_secs_f64() * 1000.0,
+                                        exclusive.as_secs_f64() * 1000.0,
                                     ),
+                                    ..NodeAttrs::default()
                                 },
-                                children: vec![],
+                                children: tracer.take_nodes(),
                             }],
                         },
                         properties: Properties {
-                            agent_attributes: agent_attrs,
-                            user_attributes: UserAttrs::default(),
+                            agent_attributes: filter
+                                .agent_attrs(&agent_attrs, Destinations::TXN_TRACE),
+                            user_attributes: filter
+                                .user_attrs(&user_attrs, Destinations::TXN_TRACE),
                             intrinsics: Intrinsics {
-                                total_time: duration.as_secs_f64(),
+                                total_time: total_time.as_secs_f64(),
+                                trip_id: trip_id.clone(),
+                                path_hash: path_hash.clone(),
                             },
                         },
                     },
-                    cat_guid: "".to_owned(),
+                    cat_guid: if path_hash.is_some() {
+                        guid.clone()
+                    } else {
+                        "".to_owned()
+                    },
                     reserved1: (),
                     force_persist: false,
                     xray_session: (),
@@ -239,32 +1007,218 @@ impl TransactionInner {
 
 #[derive(Debug)]
 struct TransactionState {
-    threads: Vec<Thread>,
+    name: String,
+    /// The transaction id used in distributed tracing.
+    guid: String,
+    trace_id: String,
+    /// Whether the trace is sampled, once decided.
+    sampled: Option<bool>,
+    /// The trace context accepted from the caller.
+    inbound: Option<InboundPayload>,
+    /// Whether the trace context has been sent to a callee.
+    distributed_trace_sent: bool,
+    cross_process: TxnCrossProcess,
+    web_request: Option<WebRequest>,
+    queue_duration: Option<Duration>,
+    ignored: bool,
+    /// Set once the name is sent by cross application tracing.
+    name_frozen: bool,
+    tracer: Tracer,
+    errors: Vec<ErrorData>,
+    user_attrs: UserAttrs,
+    web_response: Option<WebResponse>,
+    priority: Priority,
 }
 
 impl TransactionState {
-    fn new(now: Instant) -> Self {
+    fn new(config: &Config, now: Instant, name: &str) -> Self {
         Self {
-            threads: vec![Thread::new(now)],
+            name: name.to_owned(),
+            guid: new_guid(),
+            trace_id: new_trace_id(),
+            sampled: None,
+            inbound: None,
+            distributed_trace_sent: false,
+            cross_process: TxnCrossProcess::default(),
+            web_request: None,
+            queue_duration: None,
+            ignored: false,
+            name_frozen: false,
+            tracer: Tracer::new(
+                now,
+                config.transaction_tracer.segment_threshold,
+                config.distributed_tracer.enabled && config.span_events.enabled,
+            ),
+            errors: vec![],
+            user_attrs: UserAttrs::default(),
+            web_response: None,
+            priority: Priority::new(),
+        }
+    }
+
+    /// Decides whether the trace is sampled unless the caller has decided it.
+    ///
+    /// Nothing is sampled before the application connects.
+    fn sampled(&mut self, run: Option<&AppRun>) -> bool {
+        if let Some(sampled) = self.sampled {
+            return sampled;
+        }
+        let sampled =
+            run.is_some_and(|run| run.sampler.compute_sampled(self.priority, Instant::now()));
+        if sampled {
+            self.priority = self.priority.boost();
+        }
+        self.sampled = Some(sampled);
+        sampled
+    }
+
+    /// `cat_run` is given if cross application tracing is enabled.
+    fn set_web_request(
+        &mut self,
+        web_request: WebRequest,
+        txn_start: SystemTime,
+        cat_run: Option<&AppRun>,
+    ) {
+        if let Some(run) = cat_run {
+            self.cross_process.accept_request(&web_request.headers, run);
         }
+        self.queue_duration = web_request.queue_duration(txn_start);
+        self.web_request = Some(web_request);
     }
 }
 
-#[derive(Debug)]
-struct Thread {
-    start: Instant,
-    end: Option<Instant>,
+/// The current run, if cross application tracing is enabled.
+fn cross_process_run(app: &ApplicationInner) -> Option<Arc<AppRun>> {
+    if cross_process::enabled(&app.config) {
+        app.current_run()
+    } else {
+        None
+    }
 }
 
-impl Thread {
-    fn new(now: Instant) -> Self {
-        Self {
-            start: now,
-            end: None,
+fn distributed_trace_intrinsics(
+    guid: &str,
+    trace_id: &str,
+    priority: Priority,
+    sampled: bool,
+    inbound: Option<&InboundPayload>,
+    start: SystemTime,
+) -> DistributedTraceIntrinsics {
+    let nr = inbound.and_then(|inbound| inbound.nr.as_ref());
+    DistributedTraceIntrinsics {
+        parent_type: nr.map(|nr| nr.parent_type.as_str().to_owned()),
+        parent_app: nr.map(|nr| nr.app.clone()),
+        parent_account: nr.map(|nr| nr.account.clone()),
+        parent_transport_type: inbound.map(|inbound| inbound.transport_type.to_owned()),
+        parent_transport_duration: inbound
+            .and_then(|inbound| inbound.transport_duration(start))
+            .map(|d| d.as_secs_f64()),
+        parent_id: nr.and_then(|nr| nr.transaction_id.clone()),
+        parent_span_id: inbound.and_then(|inbound| inbound.parent_span_id.clone()),
+        guid: guid.to_owned(),
+        trace_id: trace_id.to_owned(),
+        priority,
+        sampled,
+    }
+}
+
+fn add_caller_metrics(
+    metric_table: &mut MetricTable,
+    inbound: Option<&InboundPayload>,
+    start: SystemTime,
+    duration: Duration,
+    is_web: bool,
+    has_errors: bool,
+) {
+    let caller = inbound.map_or_else(|| UNKNOWN_CALLER.to_owned(), InboundPayload::caller);
+    let select = |names: RollupNames| {
+        let web_or_other = if is_web { names.web } else { names.other };
+        [names.all, web_or_other]
+    };
+    for name in &select(duration_by_caller_metric(&caller)) {
+        metric_table.add_duration(name, None, duration, duration, true);
+    }
+    if let Some(transport_duration) = inbound.and_then(|inbound| inbound.transport_duration(start))
+    {
+        for name in &select(transport_duration_metric(&caller)) {
+            metric_table.add_duration(name, None, transport_duration, transport_duration, true);
+        }
+    }
+    if has_errors {
+        for name in &select(errors_by_caller_metric(&caller)) {
+            metric_table.add_count(name, None, 1.0, true);
         }
     }
 }
 
+fn add_request_attrs(attrs: &mut AgentAttrs, request: &WebRequest) {
+    attrs
+        .0
+        .insert("request.method".to_owned(), request.method.as_str().into());
+    attrs
+        .0
+        .insert("request.uri".to_owned(), safe_url(&request.uri).into());
+    let headers = [
+        ("request.headers.accept", http::header::ACCEPT),
+        ("request.headers.contentType", http::header::CONTENT_TYPE),
+        ("request.headers.host", http::header::HOST),
+        ("request.headers.User-Agent", http::header::USER_AGENT),
+    ];
+    for (key, name) in &headers {
+        if let Some(value) = header_str(&request.headers, name) {
+            attrs.0.insert((*key).to_owned(), value.into());
+        }
+    }
+    if let Some(length) = content_length(&request.headers) {
+        attrs
+            .0
+            .insert("request.headers.contentLength".to_owned(), length.into());
+    }
+    let referer = header_str(&request.headers, &http::header::REFERER)
+        .and_then(|referer| referer.parse::<http::Uri>().ok());
+    if let Some(referer) = referer {
+        attrs.0.insert(
+            "request.headers.referer".to_owned(),
+            safe_url(&referer).into(),
+        );
+    }
+}
+
+fn add_response_attrs(attrs: &mut AgentAttrs, response: &WebResponse) {
+    attrs.0.insert(
+        "http.statusCode".to_owned(),
+        response.status.as_u16().into(),
+    );
+    if let Some(content_type) = header_str(&response.headers, &http::header::CONTENT_TYPE) {
+        attrs.0.insert(
+            "response.headers.contentType".to_owned(),
+            content_type.into(),
+        );
+    }
+    if let Some(length) = content_length(&response.headers) {
+        attrs
+            .0
+            .insert("response.headers.contentLength".to_owned(), length.into());
+    }
+}
+
+fn header_str<'a>(
+    headers: &'a http::HeaderMap,
+    name: &http::header::HeaderName,
+) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn content_length(headers: &http::HeaderMap) -> Option<u64> {
+    header_str(headers, &http::header::CONTENT_LENGTH).and_then(|length| length.parse().ok())
+}
+
+#[derive(Debug)]
+struct WebResponse {
+    status: http::StatusCode,
+    headers: http::HeaderMap,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct WebRequest {
     pub version: http::Version,
@@ -275,6 +1229,13 @@ pub struct WebRequest {
     pub __non_exhaustive: (),
 }
 
+impl WebRequest {
+    /// Computes the queue time from the `X-Queue-Start` or `X-Request-Start` header.
+    pub(crate) fn queue_duration(&self, txn_start: SystemTime) -> Option<Duration> {
+        crate::queuing::queue_duration(&self.headers, txn_start)
+    }
+}
+
 impl From<http::request::Parts> for WebRequest {
     fn from(req: http::request::Parts) -> Self {
         WebRequest {
@@ -317,3 +1278,203 @@ impl<'a, T> From<&'a http::Request<T>> for WebRequest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A disabled application, which never connects.
+    fn test_app(configure: impl FnOnce(&mut Config)) -> Arc<ApplicationInner> {
+        let mut config = Config::new("test", "0123456789012345678901234567890123456789");
+        config.enabled = false;
+        configure(&mut config);
+        Arc::new(ApplicationInner::new(&config))
+    }
+
+    /// A disabled application, connected as `cross_process::tests::test_run`.
+    fn running_app(configure: impl FnOnce(&mut Config)) -> Arc<ApplicationInner> {
+        let app = test_app(configure);
+        let run = crate::cross_process::tests::test_run();
+        let harvest = crate::harvest::Harvest::new(&run);
+        *app.state.lock() = AppState::Running {
+            run: Arc::new(run),
+            harvest,
+        };
+        app
+    }
+
+    #[test]
+    fn test_web_attrs() {
+        let request = http::Request::get("https://example.com/users?id=1")
+            .header("Host", "example.com")
+            .header("Content-Length", "12")
+            .header("User-Agent", "curl/7.68.0")
+            .header("Referer", "https://example.com/login?next=%2Fusers")
+            .body(())
+            .unwrap();
+        let mut attrs = AgentAttrs::default();
+        add_request_attrs(&mut attrs, &WebRequest::from(&request));
+        assert_eq!(attrs.0["request.method"], "GET");
+        assert_eq!(attrs.0["request.uri"], "https://example.com/users");
+        assert_eq!(attrs.0["request.headers.host"], "example.com");
+        assert_eq!(attrs.0["request.headers.contentLength"], 12);
+        assert_eq!(attrs.0["request.headers.User-Agent"], "curl/7.68.0");
+        assert_eq!(
+            attrs.0["request.headers.referer"],
+            "https://example.com/login"
+        );
+        assert!(!attrs.0.contains_key("request.headers.accept"));
+
+        let (response, ()) = http::Response::builder()
+            .status(503)
+            .header("Content-Type", "text/plain")
+            .body(())
+            .unwrap()
+            .into_parts();
+        add_response_attrs(
+            &mut attrs,
+            &WebResponse {
+                status: response.status,
+                headers: response.headers,
+            },
+        );
+        assert_eq!(attrs.0["http.statusCode"], 503);
+        assert_eq!(attrs.0["response.headers.contentType"], "text/plain");
+    }
+
+    #[test]
+    fn test_web_response_once() {
+        let app = test_app(|_| {});
+        let txn = Transaction::new(&app, "test", None, false);
+        for &status in &[503, 500] {
+            let (response, ()) = http::Response::builder()
+                .status(status)
+                .body(())
+                .unwrap()
+                .into_parts();
+            txn.set_web_response(&response);
+        }
+        let state = txn.inner.state.lock();
+        let state = state.as_ref().unwrap();
+        assert_eq!(state.errors.len(), 1);
+        assert_eq!(state.web_response.as_ref().unwrap().status, 503);
+    }
+
+    #[test]
+    fn test_frozen_name() {
+        let app = running_app(|_| {});
+        let caller = Transaction::new(&app, "caller", None, false);
+        let mut request = http::Request::get("/users").body(()).unwrap();
+        caller.insert_distributed_trace_headers(request.headers_mut());
+        caller.set_name("renamed");
+
+        let callee = Transaction::new(&app, "callee", None, false);
+        callee.set_web_request(&request);
+        callee.set_name("first");
+        let mut response = http::HeaderMap::new();
+        callee.insert_response_headers(&mut response);
+        assert!(response.contains_key("X-NewRelic-App-Data"));
+        callee.set_name("second");
+
+        assert_eq!(caller.inner.state.lock().as_ref().unwrap().name, "caller");
+        assert_eq!(callee.inner.state.lock().as_ref().unwrap().name, "first");
+    }
+
+    #[test]
+    fn test_accept_sampled_after_segment() {
+        for &sampled in &[true, false] {
+            let app = running_app(|config| config.distributed_tracer.enabled = true);
+            // Exhaust the sampler so that it would not sample the transaction by itself.
+            let run = app.current_run().unwrap();
+            for _ in 0..100 {
+                run.sampler.compute_sampled(Priority(2.0), Instant::now());
+            }
+            let txn = Transaction::new(&app, "test", None, false);
+            drop(txn.start_segment("middleware"));
+            let mut headers = http::HeaderMap::new();
+            let payload = serde_json::json!({
+                "v": [0, 1],
+                "d": {
+                    "ty": "App",
+                    "ac": "1",
+                    "ap": "1",
+                    "id": "27ddd2d8890283b4",
+                    "tx": "5569065a5b1313bd",
+                    "tr": "d6b4ba0c3a712ca1",
+                    "pr": 1.5,
+                    "sa": sampled,
+                    "ti": 1_518_469_636_025u64,
+                    "tk": "1",
+                },
+            });
+            headers.insert("newrelic", payload.to_string().parse().unwrap());
+            txn.accept_distributed_trace_headers(&headers);
+            txn.inner.stop();
+            let state = app.state.lock();
+            if let AppState::Running { harvest, .. } = &*state {
+                assert_eq!(harvest.span_events.is_empty(), !sampled);
+            } else {
+                unreachable!();
+            }
+        }
+    }
+
+    #[test]
+    fn test_current_transaction() {
+        let app = test_app(|_| {});
+        assert!(Transaction::current_in(&app).is_none());
+        let outer = Transaction::new(&app, "outer", None, false);
+        {
+            let inner = Transaction::new(&app, "inner", None, false);
+            let current = Transaction::current_in(&app).unwrap();
+            assert!(Arc::ptr_eq(&current.inner, &inner.inner));
+            inner.inner.stop();
+            let current = Transaction::current_in(&app).unwrap();
+            assert!(Arc::ptr_eq(&current.inner, &outer.inner));
+            // The transactions locked by the panicking thread are skipped.
+            let _state = outer.inner.state.lock();
+            assert!(Transaction::current_in(&app).is_none());
+        }
+        drop(outer);
+        assert!(Transaction::current_in(&app).is_none());
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_instrumented() {
+        use crate::InstrumentNewRelic;
+
+        let app = test_app(|_| {});
+        let txn = Transaction::new(&app, "txn", None, false);
+        let worker = txn.new_thread();
+        let fut = async {
+            let current = Transaction::current().unwrap();
+            assert!(Arc::ptr_eq(&current.inner, &worker.inner));
+            assert_eq!(current.thread_id, worker.thread_id);
+        }
+        .instrument_newrelic(&worker);
+        futures_executor::block_on(fut);
+        assert_eq!(Transaction::current().unwrap().thread_id, MAIN_THREAD_ID);
+        drop(txn);
+        assert!(Transaction::current().is_none());
+    }
+
+    #[test]
+    fn test_rename_and_promote() {
+        let app = test_app(|_| {});
+        let txn = Transaction::new(&app, "job", None, false);
+        txn.set_name("/users");
+        txn.set_web_request(http::Request::get("/users/1").body(()).unwrap());
+        txn.ignore();
+        {
+            let state = txn.inner.state.lock();
+            let state = state.as_ref().unwrap();
+            assert_eq!(state.name, "/users");
+            assert!(state.web_request.is_some());
+            assert!(state.ignored);
+        }
+        txn.inner.stop();
+        txn.set_name("/posts");
+        assert!(txn.inner.state.lock().is_none());
+    }
+}
@@ -87,10 +87,10 @@ fn ip_addresses() -> std::io::Result<Vec<String>> {
     socket.connect("newrelic.com:10002")?;
     let addr = socket.local_addr()?;
     if addr.ip().is_loopback() || addr.ip().is_unspecified() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Unexpected connection address: {:?}", addr),
-        ));
+        return Err(std::io::Error::other(format!(
+            "Unexpected connection address: {:?}",
+            addr
+        )));
     }
     let ip = addr.ip();
     let ifaces = get_if_addrs::get_if_addrs()?;
@@ -267,7 +267,7 @@ fn find_docker_id(s: &str) -> Option<&str> {
     let mut start = 0;
     for i in 0..s.len() {
         let byte = s.as_bytes()[i];
-        if (b'0'..=b'9').contains(&byte) || (b'a'..=b'f').contains(&byte) {
+        if byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte) {
             // continue
         } else if i - start >= DOCKER_ID_LENGTH {
             return Some(&s[start..i]);